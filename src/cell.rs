@@ -0,0 +1,181 @@
+/**
+ * cell.rs: Encodes blocks of pixels as terminal cells.
+ */
+
+use std::f32::consts::PI;
+
+use nalgebra::{Vector2, Vector3};
+
+use crate::color;
+use crate::palette::Palette;
+
+// Quadrant glyphs by mask, bits running row by row from the top left.
+const QUADRANTS: [char; 16] = [
+  ' ', '▘', '▝', '▀', '▖', '▌', '▞', '▛', '▗', '▚', '▐', '▜', '▄', '▙', '▟', '█',
+];
+
+// Braille dot (bit of the code point) for each pixel of the 2x4 block.
+const BRAILLE_DOTS: [u32; 8] = [0, 3, 1, 4, 2, 5, 6, 7];
+
+// Characters from least to most ink, for increasing luma.
+const RAMP: &[u8] = b" .:-=+*#%@";
+
+// Luma difference across a cell above which an edge character is drawn.
+const EDGE_THRESHOLD: f32 = 0.5;
+
+// Saturation below which ASCII tints are white.
+const TINT_SATURATION: f32 = 0.5;
+
+// Glyphs used to draw each cell, and so how many pixels it carries.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Glyphs {
+  // One pixel per cell, drawn as a space over its background color.
+  Space,
+  // Sub-cell pixels drawn as a glyph in two colors, the foreground over the
+  //   background.  Half blocks stack two pixels vertically; quadrants,
+  //   sextants and Braille patterns split the cell into 2x2, 2x3 and 2x4.
+  HalfBlocks,
+  Quadrants,
+  Sextants,
+  Braille,
+  // Characters by luma, from 2x2 pixels.  Optionally, sharp changes across
+  //   the cell become edge characters (| / - \) along them, and the
+  //   characters are tinted with the nearest of the 16 basic colors.
+  Ascii { edges: bool, tint: bool },
+}
+
+impl Glyphs {
+  // Pixels per cell, horizontally and vertically.
+  pub fn pixels(&self) -> (usize, usize) {
+    match self {
+      Glyphs::Space => (1, 1),
+      Glyphs::HalfBlocks => (1, 2),
+      Glyphs::Quadrants => (2, 2),
+      Glyphs::Sextants => (2, 3),
+      Glyphs::Braille => (2, 4),
+      Glyphs::Ascii { .. } => (2, 2),
+    }
+  }
+
+  // SGR sequence and glyph for a cell's pixels, listed row by row from the
+  //   top, in `palette`.  Uncovered pixels (None) are left to the
+  //   terminal's colors.
+  pub fn encode(&self, pixels: &[Option<Vector3<u8>>], palette: Palette) -> (String, char) {
+    let (fg, bg) = (|c: &Vector3<u8>| palette.sgr(c, false), |c: &Vector3<u8>| palette.sgr(c, true));
+    match (self, pixels) {
+      (Glyphs::Space, [Some(c)]) => return (format!("\x1b[{}m", bg(c)), ' '),
+      (Glyphs::Ascii { edges, tint }, _) => return ascii(pixels, *edges, *tint),
+      _ => (),
+    }
+
+    match split(pixels) {
+      (0, _, Some(back)) => (format!("\x1b[{}m", bg(&back)), ' '),
+      (0, _, None) => (String::from("\x1b[0m"), ' '),
+      (mask, front, Some(back)) => (format!("\x1b[{};{}m", fg(&front), bg(&back)), self.glyph(mask)),
+      (mask, front, None) => (format!("\x1b[0;{}m", fg(&front)), self.glyph(mask)),
+    }
+  }
+
+  // Glyph covering the pixels set in `mask`.
+  fn glyph(&self, mask: u32) -> char {
+    match self {
+      Glyphs::Space | Glyphs::Ascii { .. } => ' ',
+      Glyphs::HalfBlocks => [' ', '▀', '▄', '█'][mask as usize],
+      Glyphs::Quadrants => QUADRANTS[mask as usize],
+      // Symbols for Legacy Computing skip the patterns that already exist
+      //   as blocks.
+      Glyphs::Sextants => match mask {
+        0 => ' ',
+        21 => '▌',
+        42 => '▐',
+        63 => '█',
+        m => char::from_u32(0x1fb00 + m - 1 - (m > 21) as u32 - (m > 42) as u32).unwrap(),
+      },
+      Glyphs::Braille => {
+        let dots: u32 = BRAILLE_DOTS.iter().enumerate().filter(|(i, _)| mask >> i & 1 == 1).map(|(_, d)| 1 << d).sum();
+        char::from_u32(0x2800 + dots).unwrap()
+      },
+    }
+  }
+}
+
+// Best two-color approximation of a block: the mask of pixels in the
+//   foreground, with the mean color of each side.  Any uncovered pixel
+//   keeps the terminal's background, leaving the covered ones to the
+//   foreground.
+fn split(pixels: &[Option<Vector3<u8>>]) -> (u32, Vector3<u8>, Option<Vector3<u8>>) {
+  let n = pixels.len();
+  let colors: Vec<Vector3<f32>> = pixels.iter().map(|p| p.unwrap_or(Vector3::zeros()).cast()).collect();
+  let mean = |mask: u32| -> Vector3<u8> {
+    let (sum, count) = colors.iter().enumerate().filter(|(i, _)| mask >> i & 1 == 1)
+      .fold((Vector3::zeros(), 0.0), |(sum, count), (_, c)| (sum + c, count + 1.0));
+    (sum / count).map(|x: f32| x.round() as u8)
+  };
+
+  let covered = pixels.iter().enumerate().filter(|(_, p)| p.is_some()).map(|(i, _)| 1 << i).sum();
+  if covered != (1 << n) - 1 {
+    return (covered, match covered {
+      0 => Vector3::zeros(),
+      _ => mean(covered),
+    }, None);
+  }
+
+  // Minimizing the squared error within each side is maximizing the sum of
+  //   |side's sum|^2 / side's count.  A mask and its complement split the
+  //   block alike, so the last pixel stays in the background.
+  let score = |mask: u32| -> f32 {
+    let (mut sums, mut counts) = ([Vector3::zeros(); 2], [0.0; 2]);
+    for (i, c) in colors.iter().enumerate() {
+      let side = (mask >> i & 1) as usize;
+      sums[side] += c;
+      counts[side] += 1.0;
+    }
+    (0..2).filter(|&s| counts[s] > 0.0).map(|s| sums[s].norm_squared() / counts[s]).sum()
+  };
+  let best = (0..1 << (n - 1)).max_by(|a, b| score(*a).total_cmp(&score(*b)).then(b.cmp(a))).unwrap();
+
+  let background = mean(!best & ((1 << n) - 1));
+  (best, match best {
+    0 => background,
+    _ => mean(best),
+  }, Some(background))
+}
+
+// Luma ramp or edge character for a 2x2 block, counting uncovered pixels
+//   as black, and its tint if any.
+fn ascii(pixels: &[Option<Vector3<u8>>], edges: bool, tint: bool) -> (String, char) {
+  let luma: Vec<f32> = pixels.iter().map(|p| p.map_or(0.0, |c| color::luma(&(c.cast::<f32>() / 255.0)))).collect();
+  let mean = luma.iter().sum::<f32>() / luma.len() as f32;
+  // Towards the brighter side, with y pointing down.
+  let gradient = Vector2::new(luma[1] + luma[3] - luma[0] - luma[2], luma[2] + luma[3] - luma[0] - luma[1]);
+
+  let glyph = match edges && gradient.norm() > EDGE_THRESHOLD {
+    true => {
+      // Edges run across the gradient.
+      let angle = gradient.y.atan2(gradient.x).rem_euclid(PI);
+      ['|', '/', '-', '\\', '|'][(angle / (PI / 4.0)).round() as usize]
+    },
+    false => RAMP[((mean * RAMP.len() as f32) as usize).min(RAMP.len() - 1)] as char,
+  };
+
+  let covered: Vec<Vector3<f32>> = pixels.iter().flatten().map(|c| c.cast()).collect();
+  let style = match (tint, covered.len()) {
+    (true, n) if n > 0 => {
+      // The character already carries the brightness, so only the hue is
+      //   matched: each ANSI color's index has a bit per primary.
+      let c = covered.iter().sum::<Vector3<f32>>() / n as f32;
+      let (min, max) = (c.min(), c.max());
+      let index = match max > 0.0 && (max - min) / max >= TINT_SATURATION {
+        true => (0..3).filter(|&i| (c[i] - min) / (max - min) > 0.5).map(|i| 1 << i).sum(),
+        false => 7,
+      };
+      let bright = match mean > 0.5 {
+        true => 60,
+        false => 0,
+      };
+      format!("\x1b[{}m", 30 + index + bright)
+    },
+    _ => String::new(),
+  };
+  (style, glyph)
+}
\ No newline at end of file
@@ -0,0 +1,473 @@
+/**
+ * cli.rs: Command-line argument parser.
+ */
+
+use std::fs;
+use std::ops::RangeInclusive;
+
+use nalgebra::Vector3;
+
+use crate::material::{Alpha, Brdf, Material};
+use crate::light::Light;
+use crate::shader::{Background, Fog, Mode, NormalSpace, Shading, Wireframe};
+use crate::texture::{Filter, Wrap};
+use crate::color::Tonemap;
+use crate::post::Downsample;
+use crate::cell::Glyphs;
+use crate::palette::{Dither, Palette};
+
+pub const USAGE: &str = "\
+Usage: raster [options] [model.obj] {diffuse} {normal} {specular}
+
+Options:
+  --ambient R,G,B          Ambient color (default 0.1,0.1,0.1)
+  --diffuse-color R,G,B    Diffuse color, tints the diffuse map (default 1,1,1)
+  --specular-color R,G,B   Specular color (default 0.5,0.5,0.5)
+  --shininess N            Blinn-Phong exponent (default 32)
+  --filter [MAP=]MODE      Texture filtering: nearest, bilinear or trilinear
+                             (default trilinear), for every map or only MAP
+                             (diffuse, normal, specular, metallic, roughness,
+                             ao, emissive, height, matcap);
+                             repeatable
+  --wrap [MAP=]MODE        Texture addressing outside [0, 1]: repeat, mirror or
+                             clamp (default repeat, or clamp for MTL maps with
+                             -clamp on), for every map or only MAP; repeatable
+  --normal-space SPACE     Normal map coordinates: tangent or object
+                             (default tangent)
+  --opacity F              Opacity in [0, 1], multiplied by the diffuse map's
+                             alpha (default 1, or the MTL's d/Tr)
+  --alpha MODE             opaque, mask (alpha-tested cutout) or blend (sorted
+                             translucency) (default opaque, or blend when the
+                             MTL's opacity is below 1)
+  --alpha-cutoff F         Alpha below which masked fragments are discarded
+                             (default 0.5)
+  --brdf phong|pbr         Blinn-Phong or Cook-Torrance GGX shading (default phong)
+  --metallic F             PBR metalness in [0, 1] (default 0)
+  --roughness F            PBR roughness in [0, 1] (default 0.5)
+  --ao F                   PBR ambient occlusion in [0, 1] (default 1)
+  --emissive R,G,B         PBR emitted color (default 0,0,0)
+  --metallic-map FILE      PBR maps; each replaces its constant above
+  --roughness-map FILE
+  --ao-map FILE
+  --emissive-map FILE
+  --height-map FILE        Height map (white = raised) for parallax occlusion
+                             mapping; ignored under --shading flat|gouraud
+  --parallax-scale F       Depth of the height map, in UV units (default 0.05)
+  --parallax-steps N       Ray-marching steps through the height map, 1 to 256
+                             (default 16)
+  --matcap FILE            Lit-sphere texture for --mode matcap
+  --mode MODE              shaded, toon, matcap, or a debug view: normals,
+                             world-normals, depth, uv, tangents, bitangents,
+                             overdraw
+                             (default shaded)
+  --toon-bands N           Number of diffuse bands in toon mode (default 4)
+  --shading MODE           flat, gouraud or phong (default phong); toggle with 's'
+  --wireframe MODE         off, all or hidden (default off); toggle with 'w'
+  --outline-color R,G,B    Toon outline color (default 0,0,0)
+  --env FILE               Equirectangular environment map (any image format,
+                             including Radiance .hdr) for image-based lighting,
+                             replacing the ambient color
+  --env-intensity F        Environment brightness multiplier (default 1)
+  --background MODE        none, env or a color R,G,B (default none)
+  --fog MODE               Depth fog toward the background color (black for
+                             none): off, linear, exp or exp2 (default off)
+  --fog-range START,END    Linear fog range, as fractions of the model's depth
+                             from front to back (default 0,1)
+  --fog-density F          Exponential fog density (default 2)
+  --tonemap OP             HDR tone mapping: none (clip), reinhard or aces
+                             (default none)
+  --exposure EV            Exposure in stops (default 0); adjust with '+'/'-'
+  --ssaa N                 Supersampling: render N×N samples per cell, N from
+                             1 to 8 (default 1)
+  --ssaa-filter FILTER     box or tent downsampling (default box)
+  --glyphs MODE            space (one pixel per cell), or two-color glyphs with
+                             sub-cell pixels: half (1x2 half blocks), quadrant
+                             (2x2), sextant (2x3) or braille (2x4); or ascii,
+                             characters by brightness, with \",edges\" for
+                             edge-following characters and \",tint\" for 16-color
+                             tinting (e.g. ascii,edges,tint) (default space)
+  --colors MODE            truecolor, 256 or 16 (default: detected from
+                             COLORTERM and TERM)
+  --dither MODE            Dithering for 256 and 16 colors: none, ordered or
+                             diffusion (default none)
+  --size COLSxROWS         Print a single frame of this many cells and exit,
+                             without needing a terminal (e.g. for logs or CI)
+  --light SPEC             Add a light (repeatable), e.g.
+                             \"directional dir=-1,-1,-1\"
+                             \"point pos=0,1,2 color=1,0.8,0.6 intensity=2 atten=1,0,0.25\"
+                             \"spot pos=0,2,2 dir=0,-1,-1 inner=10 outer=20\"
+  --shadow-size N          Shadow map resolution for directional lights,
+                             0 disables shadows (default 256)
+  --ssao RADIUS            Screen-space ambient occlusion with the given sampling
+                             radius, as a fraction of the model's size (e.g. 0.1)
+  --scene FILE             Read options from FILE, one per line without the
+                             leading dashes (e.g. \"light point pos=0,1,2\")
+  -h, --help               Show this message";
+
+#[derive(Debug)]
+pub struct Options {
+  pub model: String,
+  pub diffuse: Option<String>,
+  pub normal: Option<String>,
+  pub specular: Option<String>,
+  pub metallic: Option<String>,
+  pub roughness: Option<String>,
+  pub ao: Option<String>,
+  pub emissive: Option<String>,
+  pub height: Option<String>,
+  pub parallax_scale: f32,
+  pub parallax_steps: u32,
+  pub matcap: Option<String>,
+  pub filters: Vec<(Option<String>, Filter)>,
+  pub wraps: Vec<(Option<String>, Wrap)>,
+  pub material: MaterialOptions,
+  pub normal_space: NormalSpace,
+  pub lights: Vec<Light>,
+  pub shadow_size: usize,
+  pub ssao: f32,
+  pub mode: Mode,
+  pub toon_bands: u32,
+  pub shading: Shading,
+  pub wireframe: Wireframe,
+  pub outline_color: Vector3<f32>,
+  pub tonemap: Tonemap,
+  pub exposure: f32,
+  pub env: Option<String>,
+  pub env_intensity: f32,
+  pub background: Background,
+  pub fog: Fog,
+  pub fog_range: (f32, f32),
+  pub fog_density: f32,
+  pub ssaa: u32,
+  pub ssaa_filter: Downsample,
+  pub glyphs: Glyphs,
+  // None to detect.
+  pub colors: Option<Palette>,
+  pub dither: Dither,
+  // Cells of a single printed frame, or None to run interactively.
+  pub size: Option<(usize, usize)>,
+}
+
+// Material options given on the command line.  Each one that is set
+//   overrides the model's MTL material, or the default.
+#[derive(Debug)]
+pub struct MaterialOptions {
+  pub brdf: Option<Brdf>,
+  pub ambient: Option<Vector3<f32>>,
+  pub diffuse: Option<Vector3<f32>>,
+  pub specular: Option<Vector3<f32>>,
+  pub shininess: Option<f32>,
+  pub metallic: Option<f32>,
+  pub roughness: Option<f32>,
+  pub ao: Option<f32>,
+  pub emissive: Option<Vector3<f32>>,
+  pub opacity: Option<f32>,
+  pub alpha: Option<Alpha>,
+  pub alpha_cutoff: Option<f32>,
+}
+
+impl MaterialOptions {
+  pub fn new() -> MaterialOptions {
+    MaterialOptions {
+      brdf: None,
+      ambient: None,
+      diffuse: None,
+      specular: None,
+      shininess: None,
+      metallic: None,
+      roughness: None,
+      ao: None,
+      emissive: None,
+      opacity: None,
+      alpha: None,
+      alpha_cutoff: None,
+    }
+  }
+
+  pub fn apply(&self, material: &mut Material) {
+    material.brdf = self.brdf.unwrap_or(material.brdf);
+    material.ambient = self.ambient.unwrap_or(material.ambient);
+    material.diffuse = self.diffuse.unwrap_or(material.diffuse);
+    material.specular = self.specular.unwrap_or(material.specular);
+    material.shininess = self.shininess.unwrap_or(material.shininess);
+    material.metallic = self.metallic.unwrap_or(material.metallic);
+    material.roughness = self.roughness.unwrap_or(material.roughness);
+    material.ao = self.ao.unwrap_or(material.ao);
+    material.emissive = self.emissive.unwrap_or(material.emissive);
+    material.opacity = self.opacity.unwrap_or(material.opacity);
+    material.alpha = self.alpha.unwrap_or(material.alpha);
+    material.alpha_cutoff = self.alpha_cutoff.unwrap_or(material.alpha_cutoff);
+  }
+}
+
+impl Options {
+  // Returns Ok(None) when help was requested.
+  pub fn parse(args: &[String]) -> Result<Option<Options>, String> {
+    let mut out = Options {
+      model: String::new(),
+      diffuse: None,
+      normal: None,
+      specular: None,
+      metallic: None,
+      roughness: None,
+      ao: None,
+      emissive: None,
+      height: None,
+      parallax_scale: 0.05,
+      parallax_steps: 16,
+      matcap: None,
+      filters: vec![],
+      wraps: vec![],
+      material: MaterialOptions::new(),
+      normal_space: NormalSpace::Tangent,
+      lights: vec![],
+      shadow_size: 256,
+      ssao: 0.0,
+      mode: Mode::Shaded,
+      toon_bands: 4,
+      shading: Shading::Phong,
+      wireframe: Wireframe::Off,
+      outline_color: Vector3::zeros(),
+      tonemap: Tonemap::Clamp,
+      exposure: 0.0,
+      env: None,
+      env_intensity: 1.0,
+      background: Background::None,
+      fog: Fog::Off,
+      fog_range: (0.0, 1.0),
+      fog_density: 2.0,
+      ssaa: 1,
+      ssaa_filter: Downsample::Box,
+      glyphs: Glyphs::Space,
+      colors: None,
+      dither: Dither::None,
+      size: None,
+    };
+    let mut positional = vec![];
+
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+      match arg.as_str() {
+        "-h" | "--help" => return Ok(None),
+        x if x.starts_with("--") => {
+          let value = iter.next().ok_or(format!("Missing value for {}", x))?;
+          out.set(&x[2..], value)?;
+        },
+        _ => positional.push(arg.clone()),
+      }
+    }
+
+    let mut positional = positional.into_iter();
+    out.model = match positional.next() {
+      Some(x) => x,
+      None => return Ok(None),
+    };
+    out.diffuse = positional.next();
+    out.normal = positional.next();
+    out.specular = positional.next();
+
+    Ok(Some(out))
+  }
+
+  fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+    match name {
+      "ambient" => self.material.ambient = Some(parse_vec3(value)?),
+      "diffuse-color" => self.material.diffuse = Some(parse_vec3(value)?),
+      "specular-color" => self.material.specular = Some(parse_vec3(value)?),
+      "shininess" => self.material.shininess = Some(parse_f32(value)?),
+      "filter" => {
+        let (map, mode) = split_map(value);
+        self.filters.push((map, match mode {
+          "nearest" => Filter::Nearest,
+          "bilinear" => Filter::Bilinear,
+          "trilinear" => Filter::Trilinear,
+          x => return Err(format!("Unrecognized filter: {}", x)),
+        }));
+      },
+      "wrap" => {
+        let (map, mode) = split_map(value);
+        self.wraps.push((map, match mode {
+          "repeat" => Wrap::Repeat,
+          "mirror" => Wrap::MirroredRepeat,
+          "clamp" => Wrap::ClampToEdge,
+          x => return Err(format!("Unrecognized wrap mode: {}", x)),
+        }));
+      },
+      "normal-space" => self.normal_space = match value {
+        "tangent" => NormalSpace::Tangent,
+        "object" => NormalSpace::Object,
+        x => return Err(format!("Unrecognized normal space: {}", x)),
+      },
+      "opacity" => self.material.opacity = Some(parse_f32(value)?),
+      "alpha" => self.material.alpha = Some(match value {
+        "opaque" => Alpha::Opaque,
+        "mask" => Alpha::Mask,
+        "blend" => Alpha::Blend,
+        x => return Err(format!("Unrecognized alpha mode: {}", x)),
+      }),
+      "alpha-cutoff" => self.material.alpha_cutoff = Some(parse_f32(value)?),
+      "brdf" => self.material.brdf = Some(match value {
+        "phong" => Brdf::BlinnPhong,
+        "pbr" => Brdf::CookTorrance,
+        x => return Err(format!("Unrecognized BRDF: {}", x)),
+      }),
+      "metallic" => self.material.metallic = Some(parse_f32(value)?),
+      "roughness" => self.material.roughness = Some(parse_f32(value)?),
+      "ao" => self.material.ao = Some(parse_f32(value)?),
+      "emissive" => self.material.emissive = Some(parse_vec3(value)?),
+      "metallic-map" => self.metallic = Some(value.to_string()),
+      "roughness-map" => self.roughness = Some(value.to_string()),
+      "ao-map" => self.ao = Some(value.to_string()),
+      "emissive-map" => self.emissive = Some(value.to_string()),
+      "height-map" => self.height = Some(value.to_string()),
+      "parallax-scale" => self.parallax_scale = parse_f32(value)?,
+      "parallax-steps" => self.parallax_steps = parse_bounded(value, 1..=256)? as u32,
+      "matcap" => self.matcap = Some(value.to_string()),
+      "mode" => self.mode = match value {
+        "shaded" => Mode::Shaded,
+        "toon" => Mode::Toon,
+        "matcap" => Mode::Matcap,
+        "normals" => Mode::Normals,
+        "world-normals" => Mode::WorldNormals,
+        "depth" => Mode::Depth,
+        "uv" => Mode::Uv,
+        "tangents" => Mode::Tangents,
+        "bitangents" => Mode::Bitangents,
+        "overdraw" => Mode::Overdraw,
+        x => return Err(format!("Unrecognized mode: {}", x)),
+      },
+      "toon-bands" => self.toon_bands = parse_usize(value)? as u32,
+      "shading" => self.shading = match value {
+        "flat" => Shading::Flat,
+        "gouraud" => Shading::Gouraud,
+        "phong" => Shading::Phong,
+        x => return Err(format!("Unrecognized shading: {}", x)),
+      },
+      "wireframe" => self.wireframe = match value {
+        "off" => Wireframe::Off,
+        "all" => Wireframe::All,
+        "hidden" => Wireframe::HiddenLine,
+        x => return Err(format!("Unrecognized wireframe mode: {}", x)),
+      },
+      "outline-color" => self.outline_color = parse_vec3(value)?,
+      "tonemap" => self.tonemap = match value {
+        "none" => Tonemap::Clamp,
+        "reinhard" => Tonemap::Reinhard,
+        "aces" => Tonemap::Aces,
+        x => return Err(format!("Unrecognized tone mapping operator: {}", x)),
+      },
+      "exposure" => self.exposure = parse_f32(value)?,
+      "env" => self.env = Some(value.to_string()),
+      "env-intensity" => self.env_intensity = parse_f32(value)?,
+      "background" => self.background = match value {
+        "none" => Background::None,
+        "env" => Background::Environment,
+        x => Background::Color(parse_vec3(x).map_err(|_| format!("Unrecognized background: {}", x))?),
+      },
+      "fog" => self.fog = match value {
+        "off" => Fog::Off,
+        "linear" => Fog::Linear,
+        "exp" => Fog::Exponential,
+        "exp2" => Fog::ExponentialSquared,
+        x => return Err(format!("Unrecognized fog mode: {}", x)),
+      },
+      "fog-range" => self.fog_range = match value.split_once(',') {
+        Some((start, end)) => (parse_f32(start)?, parse_f32(end)?),
+        None => return Err(format!("Expected START,END: {}", value)),
+      },
+      "fog-density" => self.fog_density = parse_f32(value)?,
+      "ssaa" => self.ssaa = parse_bounded(value, 1..=8)? as u32,
+      "ssaa-filter" => self.ssaa_filter = match value {
+        "box" => Downsample::Box,
+        "tent" => Downsample::Tent,
+        x => return Err(format!("Unrecognized filter: {}", x)),
+      },
+      "glyphs" => self.glyphs = match value.split(',').collect::<Vec<&str>>()[..] {
+        ["space"] => Glyphs::Space,
+        ["half"] => Glyphs::HalfBlocks,
+        ["quadrant"] => Glyphs::Quadrants,
+        ["sextant"] => Glyphs::Sextants,
+        ["braille"] => Glyphs::Braille,
+        ["ascii", ref flags @ ..] => {
+          if let Some(x) = flags.iter().find(|x| !["edges", "tint"].contains(x)) {
+            return Err(format!("Unrecognized ASCII option: {}", x));
+          }
+          Glyphs::Ascii { edges: flags.contains(&"edges"), tint: flags.contains(&"tint") }
+        },
+        _ => return Err(format!("Unrecognized glyphs: {}", value)),
+      },
+      "colors" => self.colors = match value {
+        "truecolor" | "24bit" => Some(Palette::TrueColor),
+        "256" => Some(Palette::Ansi256),
+        "16" => Some(Palette::Ansi16),
+        x => return Err(format!("Unrecognized color mode: {}", x)),
+      },
+      "dither" => self.dither = match value {
+        "none" => Dither::None,
+        "ordered" => Dither::Ordered,
+        "diffusion" => Dither::Diffusion,
+        x => return Err(format!("Unrecognized dithering: {}", x)),
+      },
+      "size" => self.size = match value.split_once('x') {
+        Some((w, h)) => match (parse_usize(w)?, parse_usize(h)?) {
+          (0, _) | (_, 0) => return Err(format!("Invalid size: {}", value)),
+          size => Some(size),
+        },
+        None => return Err(format!("Expected COLSxROWS: {}", value)),
+      },
+      "light" => self.lights.push(Light::parse(value)?),
+      "shadow-size" => self.shadow_size = parse_usize(value)?,
+      "ssao" => self.ssao = parse_f32(value)?,
+      "scene" => self.load_scene(value)?,
+      x => return Err(format!("Unrecognized option: --{}", x)),
+    }
+    Ok(())
+  }
+
+  fn load_scene(&mut self, path: &str) -> Result<(), String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    for line in text.lines() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+
+      let (name, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+      self.set(name, value.trim())?;
+    }
+    Ok(())
+  }
+}
+
+// Splits "MAP=VALUE" into its parts, or returns just VALUE.
+fn split_map(s: &str) -> (Option<String>, &str) {
+  match s.split_once('=') {
+    Some((map, value)) => (Some(map.to_string()), value),
+    None => (None, s),
+  }
+}
+
+pub fn parse_f32(s: &str) -> Result<f32, String> {
+  s.parse::<f32>().map_err(|_| format!("Invalid number: {}", s))
+}
+
+pub fn parse_usize(s: &str) -> Result<usize, String> {
+  s.parse::<usize>().map_err(|_| format!("Invalid integer: {}", s))
+}
+
+// An integer within `range`, for counts that multiply per-pixel work.
+pub fn parse_bounded(s: &str, range: RangeInclusive<usize>) -> Result<usize, String> {
+  match parse_usize(s)? {
+    n if range.contains(&n) => Ok(n),
+    n => Err(format!("Expected {} to {}: {}", range.start(), range.end(), n)),
+  }
+}
+
+pub fn parse_vec3(s: &str) -> Result<Vector3<f32>, String> {
+  let parts = s.split(',').map(parse_f32).collect::<Result<Vec<f32>, String>>()?;
+  match parts[..] {
+    [x, y, z] => Ok(Vector3::new(x, y, z)),
+    [x] => Ok(Vector3::new(x, x, x)),
+    _ => Err(format!("Expected 1 or 3 comma-separated values: {}", s)),
+  }
+}
@@ -0,0 +1,54 @@
+/**
+ * color.rs: Color space conversions.
+ */
+
+use nalgebra::Vector3;
+
+// sRGB transfer function, from an encoded value in [0, 1] to linear light.
+pub fn srgb_to_linear(c: f32) -> f32 {
+  match c <= 0.04045 {
+    true => c / 12.92,
+    false => ((c + 0.055) / 1.055).powf(2.4),
+  }
+}
+
+pub fn linear_to_srgb(c: f32) -> f32 {
+  match c <= 0.0031308 {
+    true => c * 12.92,
+    false => 1.055 * c.powf(1.0 / 2.4) - 0.055,
+  }
+}
+
+pub fn decode(c: Vector3<f32>) -> Vector3<f32> {
+  c.map(srgb_to_linear)
+}
+
+// Linear color to 8-bit sRGB, clamping anything outside [0, 1].
+pub fn encode(c: Vector3<f32>) -> Vector3<u8> {
+  c.map(|x| (linear_to_srgb(x.clamp(0.0, 1.0)) * 255.0).round() as u8)
+}
+
+// Rec. 709 luma of an encoded color in [0, 1].
+pub fn luma(c: &Vector3<f32>) -> f32 {
+  c.dot(&Vector3::new(0.2126, 0.7152, 0.0722))
+}
+
+// Operator mapping HDR linear color into [0, 1].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tonemap {
+  // Clips at 1, blowing out highlights.
+  Clamp,
+  Reinhard,
+  // Narkowicz's fit of the ACES filmic curve.
+  Aces,
+}
+
+impl Tonemap {
+  pub fn apply(&self, c: Vector3<f32>) -> Vector3<f32> {
+    match self {
+      Tonemap::Clamp => c,
+      Tonemap::Reinhard => c.map(|x| x / (1.0 + x)),
+      Tonemap::Aces => c.map(|x| (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)),
+    }
+  }
+}
\ No newline at end of file
@@ -0,0 +1,100 @@
+/**
+ * environment.rs: Image-based lighting from an equirectangular map.
+ */
+
+use std::f32::consts::PI;
+
+use nalgebra::{Vector2, Vector3};
+
+use crate::texture::{Texture, Wrap};
+
+// Grid over which the map is projected onto spherical harmonics, sampled
+//   from the mipmap level closest to its width.
+const SH_WIDTH: usize = 64;
+const SH_HEIGHT: usize = 32;
+
+// Convolution of each spherical harmonics band with the clamped cosine.
+const SH_BANDS: [f32; 9] = [
+  PI,
+  2.0 * PI / 3.0, 2.0 * PI / 3.0, 2.0 * PI / 3.0,
+  PI / 4.0, PI / 4.0, PI / 4.0, PI / 4.0, PI / 4.0,
+];
+
+#[derive(Debug)]
+pub struct Environment {
+  texture: Texture,
+  // Radiance projected onto the first nine real spherical harmonics.
+  sh: [Vector3<f32>; 9],
+  pub intensity: f32,
+}
+
+impl Environment {
+  pub fn load(filename: &String) -> Environment {
+    let mut texture = Texture::srgb();
+    // Longitude wraps around, but latitude stops at the poles: blending
+    //   the top rows with the bottom ones would bleed one pole into the other.
+    texture.wrap = [Wrap::Repeat, Wrap::ClampToEdge];
+    texture.load(filename);
+
+    let lod = (texture.max_lod() - SH_WIDTH.ilog2() as f32).max(0.0);
+    let cell = (2.0 * PI / SH_WIDTH as f32) * (PI / SH_HEIGHT as f32);
+    let mut sh = [Vector3::zeros(); 9];
+    for y in 0..SH_HEIGHT {
+      for x in 0..SH_WIDTH {
+        let uv = Vector2::new((x as f32 + 0.5) / SH_WIDTH as f32, (y as f32 + 0.5) / SH_HEIGHT as f32);
+        let dir = direction(uv);
+        // Cells shrink towards the poles.
+        let solid_angle = cell * (1.0 - dir.y * dir.y).sqrt();
+        let radiance = texture.sample_lod(uv, lod).xyz() * solid_angle;
+        for (c, y) in sh.iter_mut().zip(basis(&dir)) {
+          *c += radiance * y;
+        }
+      }
+    }
+
+    Environment {
+      texture,
+      sh,
+      intensity: 1.0,
+    }
+  }
+
+  // Light diffusely reflected by a white surface facing `n` (world space),
+  //   i.e. irradiance over pi.
+  pub fn irradiance(&self, n: &Vector3<f32>) -> Vector3<f32> {
+    let e: Vector3<f32> = self.sh.iter().zip(basis(n)).zip(SH_BANDS).map(|((c, y), a)| c * y * a).sum();
+    e.map(|x| x.max(0.0)) * (self.intensity / PI)
+  }
+
+  // Light arriving from `dir` (world space).  Rougher surfaces read coarser
+  //   mipmaps, standing in for a prefiltered specular map.
+  pub fn radiance(&self, dir: &Vector3<f32>, roughness: f32) -> Vector3<f32> {
+    self.texture.sample_lod(uv(dir), roughness * self.texture.max_lod()).xyz() * self.intensity
+  }
+}
+
+// Equirectangular mapping: u is the longitude, with -z at the center of the
+//   map, and v the latitude from -y (v = 0) to +y.
+fn uv(dir: &Vector3<f32>) -> Vector2<f32> {
+  let dir = dir.normalize();
+  Vector2::new(0.5 + dir.x.atan2(-dir.z) / (2.0 * PI), 0.5 + dir.y.clamp(-1.0, 1.0).asin() / PI)
+}
+
+fn direction(uv: Vector2<f32>) -> Vector3<f32> {
+  let (lon, lat) = ((uv.x - 0.5) * 2.0 * PI, (uv.y - 0.5) * PI);
+  Vector3::new(lon.sin() * lat.cos(), lat.sin(), -lon.cos() * lat.cos())
+}
+
+fn basis(d: &Vector3<f32>) -> [f32; 9] {
+  [
+    0.282095,
+    0.488603 * d.y,
+    0.488603 * d.z,
+    0.488603 * d.x,
+    1.092548 * d.x * d.y,
+    1.092548 * d.y * d.z,
+    0.315392 * (3.0 * d.z * d.z - 1.0),
+    1.092548 * d.x * d.z,
+    0.546274 * (d.x * d.x - d.y * d.y),
+  ]
+}
\ No newline at end of file
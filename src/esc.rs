@@ -14,6 +14,7 @@ pub struct EscSeq {
   current_arg: u32,
 
   pub is_mouse: bool,
+  pub is_key: bool,
   pub args: Vec<u32>,
   pub command: char,
 }
@@ -23,6 +24,7 @@ impl EscSeq {
     EscSeq {
       state: States::Esc,
       is_mouse: false,
+      is_key: false,
       args: vec![],
       current_arg: 0,
       command: ' ',
@@ -33,6 +35,11 @@ impl EscSeq {
       States::Esc => {
         if b == '\x1b' {
           self.state = States::Square;
+        } else {
+          // Plain keypress
+          self.is_key = true;
+          self.command = b;
+          return true;
         }
       },
       States::Square => {
@@ -66,6 +73,7 @@ impl EscSeq {
   pub fn reset(&mut self) {
     self.state = States::Esc;
     self.is_mouse = false;
+    self.is_key = false;
     self.args.clear();
     self.current_arg = 0;
     self.command = ' ';
@@ -0,0 +1,120 @@
+/**
+ * framebuffer.rs: Per-pixel depth, geometry and color buffers.
+ */
+
+use nalgebra::{Vector2, Vector3, Vector4};
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::vertex::Vertex;
+
+// Nearest surface covering a pixel.
+#[derive(Debug, Clone, Copy)]
+pub struct Fragment {
+  pub face: usize,
+  pub depth: f32,
+  pub bar: Vector3<f32>,
+}
+
+#[derive(Debug)]
+pub struct Framebuffer {
+  pub width: i32,
+  pub height: i32,
+
+  pub fragments: Vec<Option<Fragment>>,
+  // Translucent fragments and their alpha, blended over the opaque image.
+  pub translucent: Vec<(usize, Fragment, f32)>,
+  // Number of fragments that reached each pixel before depth testing.
+  pub overdraw: Vec<u32>,
+
+  // View-space geometry of the visible surface (zero where uncovered).
+  pub position: Vec<Vector3<f32>>,
+  pub normal: Vec<Vector3<f32>>,
+  pub uv: Vec<Vector2<f32>>,
+
+  pub ao: Vec<f32>,
+  pub color: Vec<Vector4<f32>>,
+}
+
+impl Framebuffer {
+  pub fn new(w: i32, h: i32) -> Framebuffer {
+    let len = (w * h) as usize;
+    Framebuffer {
+      width: w,
+      height: h,
+
+      fragments: vec![None; len],
+      translucent: vec![],
+      overdraw: vec![0; len],
+
+      position: vec![],
+      normal: vec![],
+      uv: vec![],
+
+      ao: vec![1.0; len],
+      color: vec![],
+    }
+  }
+
+  // Maps rasterizer coordinates (y up) to a buffer index (y down).
+  pub fn index(&self, x: i32, y: i32) -> Option<usize> {
+    let row = self.height - y;
+    if x < 0 || x >= self.width || row < 0 || row >= self.height {
+      return None;
+    }
+    Some((x + row * self.width) as usize)
+  }
+
+  pub fn depth_test(&mut self, idx: usize, frag: Fragment) {
+    self.overdraw[idx] += 1;
+    match self.fragments[idx] {
+      Some(prev) if prev.depth < frag.depth => (),
+      _ => self.fragments[idx] = Some(frag),
+    }
+  }
+
+  pub fn add_translucent(&mut self, idx: usize, frag: Fragment, alpha: f32) {
+    self.overdraw[idx] += 1;
+    self.translucent.push((idx, frag, alpha));
+  }
+
+  pub fn depth(&self, idx: usize) -> Option<f32> {
+    self.fragments[idx].map(|f| f.depth)
+  }
+
+  // Fills the position, normal and UV buffers from the visible fragments.
+  pub fn resolve_geometry(&mut self, verts: &[Vertex]) {
+    let (geometry, uv): (Vec<_>, Vec<_>) = self.fragments.par_iter().map(|frag| match frag {
+      Some(f) => {
+        let vert = &verts[f.face];
+        ((vert.view_triangle * f.bar, (vert.varying_normal * f.bar).normalize()), vert.varying_uv * f.bar)
+      },
+      None => ((Vector3::zeros(), Vector3::zeros()), Vector2::zeros()),
+    }).unzip();
+    (self.position, self.normal) = geometry.into_iter().unzip();
+    self.uv = uv;
+  }
+
+  // UV change per pixel along x and y, taken within the pixel's 2x2 quad
+  //   as on a GPU, or from the other side if that neighbor is uncovered.
+  pub fn uv_derivatives(&self, idx: usize) -> (Vector2<f32>, Vector2<f32>) {
+    let (x, y) = (idx as i32 % self.width, idx as i32 / self.width);
+    let diff = |a: (i32, i32), b: (i32, i32)| -> Option<Vector2<f32>> {
+      let i = self.covered(a.0, a.1)?;
+      let j = self.covered(b.0, b.1)?;
+      Some(self.uv[j] - self.uv[i])
+    };
+
+    let (qx, qy) = (x & !1, y & !1);
+    let ddx = diff((qx, y), (qx + 1, y)).or_else(|| diff((x - 1, y), (x, y))).or_else(|| diff((x, y), (x + 1, y)));
+    let ddy = diff((x, qy), (x, qy + 1)).or_else(|| diff((x, y - 1), (x, y))).or_else(|| diff((x, y), (x, y + 1)));
+    (ddx.unwrap_or(Vector2::zeros()), ddy.unwrap_or(Vector2::zeros()))
+  }
+
+  fn covered(&self, x: i32, y: i32) -> Option<usize> {
+    if x < 0 || y < 0 || x >= self.width || y >= self.height {
+      return None;
+    }
+    let idx = (x + y * self.width) as usize;
+    self.fragments[idx].map(|_| idx)
+  }
+}
@@ -0,0 +1,654 @@
+/**
+ * image.rs: Image file decoders.
+ */
+
+use std::error::Error;
+
+use tinytga::{Bpp, DataType, ImageOrigin, RawTga};
+use nalgebra::Vector4;
+
+// Decoded image, with texels in [0, 1] stored bottom row first, so that
+//   row 0 is at v = 0 as in OBJ texture coordinates.
+pub struct Image {
+  pub width: usize,
+  pub height: usize,
+  pub texels: Vec<Vector4<f32>>,
+  // Whether the texels hold linear, possibly unbounded, values (as in HDR
+  //   files) rather than [0, 1] encoded ones.
+  pub linear: bool,
+}
+
+// Decodes a PNG, JPEG, BMP, PPM/PGM or Radiance HDR file, detected by its
+//   magic bytes.
+//   TGA has no signature, so anything else is assumed to be one.
+pub fn decode(buf: &[u8]) -> Result<Image, Box<dyn Error>> {
+  match buf {
+    [0x89, b'P', b'N', b'G', ..] => png(buf),
+    [0xff, 0xd8, 0xff, ..] => jpeg(buf),
+    [b'B', b'M', ..] => bmp(buf),
+    [b'P', b'2' | b'3' | b'5' | b'6', ..] => ppm(buf),
+    [b'#', b'?', ..] => hdr(buf),
+    _ => tga(buf),
+  }
+}
+
+impl Image {
+  // Builds an image from top-to-bottom rows of `channels` samples each,
+  //   in [0, 1].  Gray (and gray+alpha) is spread across RGB.
+  fn from_samples(width: usize, height: usize, channels: usize, samples: &[f32]) -> Result<Image, Box<dyn Error>> {
+    if width == 0 || height == 0 {
+      return Err("Empty image".into());
+    }
+    let count = sample_count(width, height, channels)?;
+    if samples.len() < count {
+      return Err("Truncated image data".into());
+    }
+
+    let mut texels = Vec::with_capacity(width * height);
+    for row in samples[..count].chunks(width * channels).rev() {
+      for s in row.chunks(channels) {
+        let texel = match channels {
+          1 => Vector4::new(s[0], s[0], s[0], 1.0),
+          2 => Vector4::new(s[0], s[0], s[0], s[1]),
+          3 => Vector4::new(s[0], s[1], s[2], 1.0),
+          _ => Vector4::new(s[0], s[1], s[2], s[3]),
+        };
+        texels.push(texel);
+      }
+    }
+
+    Ok(Image { width, height, texels, linear: false })
+  }
+}
+
+// width * height * channels, unless a corrupt header makes it overflow.
+fn sample_count(width: usize, height: usize, channels: usize) -> Result<usize, Box<dyn Error>> {
+  width.checked_mul(height).and_then(|n| n.checked_mul(channels)).ok_or_else(|| "Invalid image dimensions".into())
+}
+
+fn png(buf: &[u8]) -> Result<Image, Box<dyn Error>> {
+  // Expands palettes, transparency chunks and sub-byte depths to 8 bits,
+  //   leaving 16-bit samples as they are.
+  let mut decoder = png::Decoder::new(buf);
+  decoder.set_transformations(png::Transformations::EXPAND);
+  let mut reader = decoder.read_info()?;
+  let mut data = vec![0; reader.output_buffer_size()];
+  let info = reader.next_frame(&mut data)?;
+  data.truncate(info.buffer_size());
+
+  let samples: Vec<f32> = match info.bit_depth {
+    png::BitDepth::Sixteen => data.chunks(2).map(|s| u16::from_be_bytes([s[0], s[1]]) as f32 / 65535.0).collect(),
+    _ => data.iter().map(|s| *s as f32 / 255.0).collect(),
+  };
+  Image::from_samples(info.width as usize, info.height as usize, info.color_type.samples(), &samples)
+}
+
+fn jpeg(buf: &[u8]) -> Result<Image, Box<dyn Error>> {
+  let mut decoder = jpeg_decoder::Decoder::new(buf);
+  let data = decoder.decode()?;
+  let info = decoder.info().ok_or("Missing JPEG header")?;
+
+  let (channels, samples): (usize, Vec<f32>) = match info.pixel_format {
+    jpeg_decoder::PixelFormat::L8 => (1, data.iter().map(|s| *s as f32 / 255.0).collect()),
+    // Lossless samples of up to 16 bits, in native byte order.
+    jpeg_decoder::PixelFormat::L16 => {
+      let max = ((1u32 << jpeg_precision(buf).ok_or("Missing JPEG frame header")?) - 1) as f32;
+      (1, data.chunks(2).map(|s| u16::from_ne_bytes([s[0], s[1]]) as f32 / max).collect())
+    },
+    jpeg_decoder::PixelFormat::RGB24 => (3, data.iter().map(|s| *s as f32 / 255.0).collect()),
+    // jpeg-decoder already undoes Adobe's inverted storage, so these are
+    //   plain ink amounts.
+    jpeg_decoder::PixelFormat::CMYK32 => (3, data.chunks(4).flat_map(|s| {
+      let k = s[3] as f32 / 255.0;
+      [s[0], s[1], s[2]].map(|c| (1.0 - c as f32 / 255.0) * (1.0 - k))
+    }).collect()),
+  };
+  Image::from_samples(info.width as usize, info.height as usize, channels, &samples)
+}
+
+// Sample precision in bits, from the first start-of-frame segment.
+//   jpeg-decoder doesn't report it.
+fn jpeg_precision(buf: &[u8]) -> Option<u8> {
+  let mut pos = 2;
+  loop {
+    let (marker, length) = match buf.get(pos..pos + 4)? {
+      [0xff, m, hi, lo] => (*m, u16::from_be_bytes([*hi, *lo]) as usize),
+      _ => return None,
+    };
+    match marker {
+      // SOF0-SOF15, except DHT, JPG and DAC, which share the range.
+      0xc0..=0xcf if marker != 0xc4 && marker != 0xc8 && marker != 0xcc => return buf.get(pos + 4).copied(),
+      _ => pos += 2 + length,
+    }
+  }
+}
+
+// Uncompressed or bitfield BMPs at 1, 4, 8, 16, 24 or 32 bits per pixel.
+fn bmp(buf: &[u8]) -> Result<Image, Box<dyn Error>> {
+  let u16_at = |i: usize| buf.get(i..i + 2).map(|b| u16::from_le_bytes([b[0], b[1]]) as u32);
+  let u32_at = |i: usize| buf.get(i..i + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+  let header = || -> Option<(u32, u32, i32, i32, u32, u32, u32)> {
+    Some((
+      u32_at(10)?,
+      u32_at(14)?,
+      u32_at(18)? as i32,
+      u32_at(22)? as i32,
+      u16_at(28)?,
+      u32_at(30)?,
+      u32_at(46).unwrap_or(0),
+    ))
+  };
+  let (offset, dib_size, width, height, bpp, compression, colors) = header().ok_or("Truncated BMP header")?;
+  if width <= 0 || height == 0 {
+    return Err("Invalid BMP dimensions".into());
+  }
+
+  // Negative heights mark top-down row order.
+  let bottom_up = height > 0;
+  let (width, height) = (width as usize, height.unsigned_abs() as usize);
+
+  // Channel masks; for bitfield images they follow the 40-byte header
+  //   (or are part of a larger one), at the same offset either way.
+  let mut masks = match bpp {
+    16 => [0x7c00, 0x03e0, 0x001f, 0],
+    _ => [0xff0000, 0xff00, 0xff, 0],
+  };
+  match compression {
+    0 => (),
+    3 | 6 => {
+      for (i, mask) in masks.iter_mut().enumerate().take(3) {
+        *mask = u32_at(54 + i * 4).ok_or("Truncated BMP bitfields")?;
+      }
+      if compression == 6 || dib_size >= 56 {
+        masks[3] = u32_at(66).unwrap_or(0);
+      }
+    },
+    _ => return Err("Compressed BMPs are not supported".into()),
+  }
+
+  let palette: Vec<[u8; 3]> = match bpp {
+    1 | 4 | 8 => {
+      let count = match colors {
+        0 => 1 << bpp,
+        n => n as usize,
+      };
+      let start = 14 + dib_size as usize;
+      let entries = buf.get(start..start + count * 4).ok_or("Truncated BMP palette")?;
+      entries.chunks(4).map(|c| [c[2], c[1], c[0]]).collect()
+    },
+    16 | 24 | 32 => vec![],
+    _ => return Err(format!("Unsupported BMP depth: {}", bpp).into()),
+  };
+
+  let stride = (width * bpp as usize).div_ceil(32) * 4;
+  let channel = |value: u32, mask: u32| match mask {
+    0 => 1.0,
+    _ => ((value & mask) >> mask.trailing_zeros()) as f32 / (mask >> mask.trailing_zeros()) as f32,
+  };
+
+  let mut rows = vec![];
+  for y in 0..height {
+    let start = offset as usize + y * stride;
+    let row = buf.get(start..start + stride).ok_or("Truncated BMP pixel data")?;
+    let mut samples = Vec::with_capacity(width * 4);
+    for x in 0..width {
+      let rgba = match bpp {
+        1 | 4 | 8 => {
+          let bit = x * bpp as usize;
+          let index = (row[bit / 8] >> (8 - bpp as usize - bit % 8)) & ((1 << bpp) - 1) as u8;
+          let c = palette.get(index as usize).ok_or("BMP palette index out of range")?;
+          [c[0] as f32 / 255.0, c[1] as f32 / 255.0, c[2] as f32 / 255.0, 1.0]
+        },
+        24 => [row[x * 3 + 2] as f32 / 255.0, row[x * 3 + 1] as f32 / 255.0, row[x * 3] as f32 / 255.0, 1.0],
+        _ => {
+          let bytes = (bpp / 8) as usize;
+          let value = row[x * bytes..(x + 1) * bytes].iter().rev().fold(0, |v, b| (v << 8) | *b as u32);
+          [channel(value, masks[0]), channel(value, masks[1]), channel(value, masks[2]), channel(value, masks[3])]
+        },
+      };
+      samples.extend_from_slice(&rgba);
+    }
+    rows.push(samples);
+  }
+
+  // from_samples expects the top row first.
+  if bottom_up {
+    rows.reverse();
+  }
+  Image::from_samples(width, height, 4, &rows.concat())
+}
+
+// Binary (P5/P6) and plain-text (P2/P3) PGM/PPM, with 8 or 16-bit samples.
+fn ppm(buf: &[u8]) -> Result<Image, Box<dyn Error>> {
+  let mut pos = 2;
+  let mut token = || -> Option<usize> {
+    loop {
+      match buf.get(pos)? {
+        b'#' => while *buf.get(pos)? != b'\n' {
+          pos += 1;
+        },
+        c if c.is_ascii_whitespace() => pos += 1,
+        _ => break,
+      }
+    }
+    let start = pos;
+    while buf.get(pos).is_some_and(u8::is_ascii_digit) {
+      pos += 1;
+    }
+    std::str::from_utf8(&buf[start..pos]).ok()?.parse().ok()
+  };
+
+  let channels = match buf[1] {
+    b'2' | b'5' => 1,
+    _ => 3,
+  };
+  let (width, height, max) = match (token(), token(), token()) {
+    (Some(w), Some(h), Some(m)) if w > 0 && h > 0 && m > 0 && m < 65536 => (w, h, m as f32),
+    _ => return Err("Invalid PPM header".into()),
+  };
+  let count = sample_count(width, height, channels)?;
+
+  let samples: Vec<f32> = match buf[1] {
+    b'2' | b'3' => (0..count).map_while(|_| token()).map(|s| s as f32 / max).collect(),
+    _ => {
+      // A single whitespace byte separates the header from the data.
+      let data = buf.get(pos + 1..).unwrap_or_default();
+      match max > 255.0 {
+        true => data.chunks_exact(2).map(|s| u16::from_be_bytes([s[0], s[1]]) as f32 / max).collect(),
+        false => data.iter().map(|s| *s as f32 / max).collect(),
+      }
+    },
+  };
+  Image::from_samples(width, height, channels, &samples)
+}
+
+// Truecolor, grayscale and color-mapped TGAs, RLE or not, at 8, 16, 24 or
+//   32 bits per pixel and with any image origin.
+fn tga(buf: &[u8]) -> Result<Image, Box<dyn Error>> {
+  let tga = RawTga::from_slice(buf).map_err(|e| format!("Unrecognized image format ({:?})", e))?;
+  let header = tga.header();
+  let (width, height) = (header.width as usize, header.height as usize);
+  if width == 0 || height == 0 {
+    return Err("Invalid TGA dimensions".into());
+  }
+
+  let mut texels = vec![Vector4::zeros(); width * height];
+  for p in tga.pixels() {
+    // Positions are relative to the top left corner, but tinytga leaves
+    //   right-to-left rows as they are.
+    let x = match header.image_origin {
+      ImageOrigin::BottomRight | ImageOrigin::TopRight => width - 1 - p.position.x as usize,
+      _ => p.position.x as usize,
+    };
+    let y = height - 1 - p.position.y as usize;
+
+    texels[x + y * width] = match header.data_type {
+      DataType::BlackAndWhite => {
+        let gray = (p.color & 0xff) as f32;
+        let alpha = match header.pixel_depth {
+          Bpp::Bits16 => (p.color >> 8) as f32,
+          _ => 255.0,
+        };
+        Vector4::new(gray, gray, gray, alpha)
+      },
+      DataType::ColorMapped => {
+        let map = tga.color_map().ok_or("Missing TGA color map")?;
+        let index = (p.color as usize).checked_sub(header.color_map_start as usize);
+        let entry = index.and_then(|i| map.get_raw(i)).ok_or("TGA color map index out of range")?;
+        tga_color(entry, map.entry_bpp(), header.alpha_channel_depth)
+      },
+      _ => tga_color(p.color, header.pixel_depth, header.alpha_channel_depth),
+    } / 255.0;
+  }
+
+  Ok(Image { width, height, texels, linear: false })
+}
+
+// Converts a raw BGR(A) value.  The top bit of 16-bit colors is only alpha
+//   if the header says so, while 32-bit colors are assumed to have it.
+fn tga_color(raw: u32, bpp: Bpp, alpha_depth: u8) -> Vector4<f32> {
+  match bpp {
+    Bpp::Bits16 => {
+      let five = |shift: u32| ((raw >> shift) & 0x1f) as f32 * 255.0 / 31.0;
+      let alpha = match alpha_depth > 0 && raw & 0x8000 == 0 {
+        true => 0.0,
+        false => 255.0,
+      };
+      Vector4::new(five(10), five(5), five(0), alpha)
+    },
+    Bpp::Bits8 => Vector4::new(raw as f32, raw as f32, raw as f32, 255.0),
+    _ => {
+      let alpha = match bpp {
+        Bpp::Bits32 => (raw >> 24) & 0xff,
+        _ => 255,
+      };
+      Vector4::new(((raw >> 16) & 0xff) as f32, ((raw >> 8) & 0xff) as f32, (raw & 0xff) as f32, alpha as f32)
+    },
+  }
+}
+
+// Radiance RGBE images, flat or with run-length encoded scanlines.
+fn hdr(buf: &[u8]) -> Result<Image, Box<dyn Error>> {
+  // Header lines end with a blank one, followed by the resolution line.
+  let mut lines = buf.split(|b| *b == b'\n');
+  let mut pos = 0;
+  for line in lines.by_ref() {
+    pos += line.len() + 1;
+    if line.starts_with(b"FORMAT=") && line != b"FORMAT=32-bit_rle_rgbe" {
+      return Err("Unsupported HDR pixel format".into());
+    }
+    if line.is_empty() {
+      break;
+    }
+  }
+  let resolution = lines.next().ok_or("Truncated HDR header")?;
+  pos += resolution.len() + 1;
+
+  let words: Vec<&str> = std::str::from_utf8(resolution)?.split_whitespace().collect();
+  let (top_down, width, height) = match words[..] {
+    [y, h, "+X", w] if y == "-Y" || y == "+Y" => (y == "-Y", w.parse::<usize>()?, h.parse::<usize>()?),
+    _ => return Err("Unsupported HDR orientation".into()),
+  };
+  if width == 0 || height == 0 {
+    return Err("Invalid HDR dimensions".into());
+  }
+  // Only rows narrower than 0x8000 pixels can be run-length encoded; flat
+  //   ones must fit in the file, which also bounds the buffers below.
+  sample_count(width, height, 4)?;
+  if width >= 0x8000 && width * 4 > buf.len() {
+    return Err("Truncated HDR pixel data".into());
+  }
+
+  let mut rgbe = vec![0u8; width * 4];
+  let mut rows = Vec::new();
+  for _ in 0..height {
+    let data = buf.get(pos..).ok_or("Truncated HDR pixel data")?;
+    match data {
+      // New-style RLE stores each of the four channels separately.
+      [2, 2, hi, lo, ..] if (8..0x8000).contains(&width) && ((*hi as usize) << 8 | *lo as usize) == width => {
+        pos += 4;
+        for c in 0..4 {
+          let mut x = 0;
+          while x < width {
+            let count = *buf.get(pos).ok_or("Truncated HDR pixel data")? as usize;
+            let (n, run) = match count > 128 {
+              true => (count - 128, true),
+              false => (count, false),
+            };
+            if n == 0 || x + n > width {
+              return Err("Invalid HDR run length".into());
+            }
+            for i in 0..n {
+              let offset = match run {
+                true => 1,
+                false => 1 + i,
+              };
+              rgbe[(x + i) * 4 + c] = *buf.get(pos + offset).ok_or("Truncated HDR pixel data")?;
+            }
+            pos += match run {
+              true => 2,
+              false => 1 + n,
+            };
+            x += n;
+          }
+        }
+      },
+      _ => {
+        rgbe.copy_from_slice(data.get(..width * 4).ok_or("Truncated HDR pixel data")?);
+        pos += width * 4;
+      },
+    }
+
+    rows.push(rgbe.chunks(4).map(|p| match p[3] {
+      0 => Vector4::new(0.0, 0.0, 0.0, 1.0),
+      e => {
+        let scale = 2f32.powi(e as i32 - 136);
+        Vector4::new(p[0] as f32 * scale, p[1] as f32 * scale, p[2] as f32 * scale, 1.0)
+      },
+    }).collect::<Vec<_>>());
+  }
+
+  if top_down {
+    rows.reverse();
+  }
+  Ok(Image { width, height, texels: rows.concat(), linear: true })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Top-left red, top-right green, bottom-left blue, bottom-right white,
+  //   as each format is written below.
+  const RED: [f32; 3] = [1.0, 0.0, 0.0];
+  const GREEN: [f32; 3] = [0.0, 1.0, 0.0];
+  const BLUE: [f32; 3] = [0.0, 0.0, 1.0];
+  const WHITE: [f32; 3] = [1.0, 1.0, 1.0];
+
+  fn rgb(image: &Image, x: usize, y: usize) -> [f32; 3] {
+    let t = image.texels[x + y * image.width];
+    [t.x, t.y, t.z]
+  }
+
+  // Checks the 2x2 test pattern, whose bottom row is stored first.
+  fn assert_pattern(image: &Image) {
+    assert_eq!((image.width, image.height), (2, 2));
+    assert_eq!(rgb(image, 0, 1), RED);
+    assert_eq!(rgb(image, 1, 1), GREEN);
+    assert_eq!(rgb(image, 0, 0), BLUE);
+    assert_eq!(rgb(image, 1, 0), WHITE);
+  }
+
+  fn png_2x2(color: png::ColorType, depth: png::BitDepth, palette: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut buf = vec![];
+    let mut encoder = png::Encoder::new(&mut buf, 2, 2);
+    encoder.set_color(color);
+    encoder.set_depth(depth);
+    if !palette.is_empty() {
+      encoder.set_palette(palette.to_vec());
+    }
+    encoder.write_header().unwrap().write_image_data(data).unwrap();
+    buf
+  }
+
+  // Lossless (SOF3) grayscale 2x2 JPEG around the entropy-coded `data`,
+  //   with a Huffman table giving every difference category a 5-bit code.
+  fn jpeg_2x2(precision: u8, data: &[u8]) -> Vec<u8> {
+    let mut buf = vec![0xff, 0xd8];
+    buf.extend_from_slice(&[0xff, 0xc4, 0, 36, 0, 0, 0, 0, 0, 17]);
+    buf.extend_from_slice(&[0; 11]);
+    buf.extend(0..17u8);
+    buf.extend_from_slice(&[0xff, 0xc3, 0, 11, precision, 0, 2, 0, 2, 1, 1, 0x11, 0]);
+    buf.extend_from_slice(&[0xff, 0xda, 0, 8, 1, 1, 0, 1, 0, 0]);
+    buf.extend_from_slice(data);
+    buf.extend_from_slice(&[0xff, 0xd9]);
+    buf
+  }
+
+  fn bmp_2x2(height: i32, rows: [[u8; 6]; 2]) -> Vec<u8> {
+    let mut buf = b"BM".to_vec();
+    buf.extend_from_slice(&[0; 8]);
+    buf.extend_from_slice(&54u32.to_le_bytes());
+    buf.extend_from_slice(&40u32.to_le_bytes());
+    buf.extend_from_slice(&2i32.to_le_bytes());
+    buf.extend_from_slice(&height.to_le_bytes());
+    buf.extend_from_slice(&1u16.to_le_bytes());
+    buf.extend_from_slice(&24u16.to_le_bytes());
+    buf.extend_from_slice(&[0; 24]);
+    // Rows are padded to four bytes.
+    for row in rows {
+      buf.extend_from_slice(&row);
+      buf.extend_from_slice(&[0; 2]);
+    }
+    buf
+  }
+
+  fn tga_2x2(descriptor: u8, rows: [[u8; 6]; 2]) -> Vec<u8> {
+    let mut buf = vec![0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 2, 0, 24, descriptor];
+    buf.extend(rows.concat());
+    buf
+  }
+
+  #[test]
+  fn png_rgb() {
+    let data = [255, 0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 255];
+    let buf = png_2x2(png::ColorType::Rgb, png::BitDepth::Eight, &[], &data);
+    assert_pattern(&decode(&buf).unwrap());
+  }
+
+  #[test]
+  fn png_palette() {
+    // Two-bit indices 0, 1 and 2, 3, packed from the high bits.
+    let palette = [255, 0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 255];
+    let buf = png_2x2(png::ColorType::Indexed, png::BitDepth::Two, &palette, &[0x10, 0xb0]);
+    assert_pattern(&decode(&buf).unwrap());
+  }
+
+  #[test]
+  fn png_gray_16_bit() {
+    let buf = png_2x2(png::ColorType::Grayscale, png::BitDepth::Sixteen, &[], &[0x80, 0x00, 0xff, 0xff, 0, 0, 0xff, 0xff]);
+    let image = decode(&buf).unwrap();
+    assert_eq!(rgb(&image, 0, 1), [32768.0 / 65535.0; 3]);
+    assert_eq!(rgb(&image, 1, 1), [1.0; 3]);
+    assert_eq!(rgb(&image, 0, 0), [0.0; 3]);
+  }
+
+  #[test]
+  fn jpeg_8_bit() {
+    // Rows 0, 255 and 128, 64.
+    let image = decode(&jpeg_2x2(8, &[0x43, 0xfa, 0x3f, 0xd1, 0x00, 0x77, 0xff, 0x00])).unwrap();
+    assert_eq!((image.width, image.height), (2, 2));
+    assert_eq!(rgb(&image, 0, 1), [0.0; 3]);
+    assert_eq!(rgb(&image, 1, 1), [1.0; 3]);
+    assert_eq!(rgb(&image, 0, 0), [128.0 / 255.0; 3]);
+    assert_eq!(rgb(&image, 1, 0), [64.0 / 255.0; 3]);
+  }
+
+  #[test]
+  fn jpeg_12_bit() {
+    // Rows 0, 4095 and 2048, 1000, scaled by the 12-bit maximum.
+    let data = [0x63, 0xff, 0x00, 0xb3, 0xff, 0x00, 0xd9, 0x00, 0x0b, 0x7c, 0xff, 0x00];
+    let image = decode(&jpeg_2x2(12, &data)).unwrap();
+    assert_eq!(rgb(&image, 0, 1), [0.0; 3]);
+    assert_eq!(rgb(&image, 1, 1), [1.0; 3]);
+    assert!((rgb(&image, 0, 0)[0] - 2048.0 / 4095.0).abs() < 1e-6);
+    assert!((rgb(&image, 1, 0)[0] - 1000.0 / 4095.0).abs() < 1e-6);
+  }
+
+  #[test]
+  fn jpeg_errors() {
+    let buf = jpeg_2x2(8, &[0x43, 0xfa, 0x3f, 0xd1, 0x00, 0x77, 0xff, 0x00]);
+    assert!(decode(&buf[..40]).is_err());
+    assert!(decode(&buf[..60]).is_err());
+  }
+
+  #[test]
+  fn ppm_binary() {
+    let mut buf = b"P6\n# comment\n2 2\n255\n".to_vec();
+    buf.extend_from_slice(&[255, 0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 255]);
+    assert_pattern(&decode(&buf).unwrap());
+  }
+
+  #[test]
+  fn ppm_plain() {
+    let image = decode(b"P3 2 2 15  15 0 0  0 15 0  0 0 15  15 15 15").unwrap();
+    assert_pattern(&image);
+  }
+
+  #[test]
+  fn pgm_16_bit() {
+    let image = decode(b"P5 1 1 1000\n\x01\xf4").unwrap();
+    assert_eq!(rgb(&image, 0, 0), [0.5; 3]);
+  }
+
+  #[test]
+  fn ppm_errors() {
+    assert!(decode(b"P6 0 0 255\n").is_err());
+    assert!(decode(b"P6 2 0 255\n").is_err());
+    assert!(decode(b"P6 1 1 0\n\0\0\0").is_err());
+    assert!(decode(b"P6 1 1 255\n\0\0").is_err());
+    assert!(decode(b"P3 1 1 255 0 0").is_err());
+    // width * height * 3 overflows.
+    assert!(decode(b"P6 4294967296 4294967296 255\n").is_err());
+  }
+
+  #[test]
+  fn bmp_bottom_up() {
+    let buf = bmp_2x2(2, [[255, 0, 0, 255, 255, 255], [0, 0, 255, 0, 255, 0]]);
+    assert_pattern(&decode(&buf).unwrap());
+  }
+
+  #[test]
+  fn bmp_top_down() {
+    let buf = bmp_2x2(-2, [[0, 0, 255, 0, 255, 0], [255, 0, 0, 255, 255, 255]]);
+    assert_pattern(&decode(&buf).unwrap());
+  }
+
+  #[test]
+  fn bmp_errors() {
+    let mut buf = bmp_2x2(0, [[0; 6]; 2]);
+    assert!(decode(&buf).is_err());
+    buf = bmp_2x2(2, [[0; 6]; 2]);
+    buf.truncate(60);
+    assert!(decode(&buf).is_err());
+  }
+
+  #[test]
+  fn tga_bottom_left() {
+    let buf = tga_2x2(0, [[255, 0, 0, 255, 255, 255], [0, 0, 255, 0, 255, 0]]);
+    assert_pattern(&decode(&buf).unwrap());
+  }
+
+  #[test]
+  fn tga_top_left() {
+    let buf = tga_2x2(0x20, [[0, 0, 255, 0, 255, 0], [255, 0, 0, 255, 255, 255]]);
+    assert_pattern(&decode(&buf).unwrap());
+  }
+
+  #[test]
+  fn tga_top_right() {
+    let buf = tga_2x2(0x30, [[0, 255, 0, 0, 0, 255], [255, 255, 255, 255, 0, 0]]);
+    assert_pattern(&decode(&buf).unwrap());
+  }
+
+  #[test]
+  fn tga_errors() {
+    let mut buf = tga_2x2(0, [[0; 6]; 2]);
+    buf[12] = 0;
+    assert!(decode(&buf).is_err());
+  }
+
+  #[test]
+  fn hdr_flat() {
+    let mut buf = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 2\n".to_vec();
+    // Mantissas over 256 scaled by 2^(exponent - 128).
+    buf.extend_from_slice(&[128, 0, 0, 129, 0, 128, 0, 129, 0, 0, 128, 129, 128, 128, 128, 129]);
+    let image = decode(&buf).unwrap();
+    assert!(image.linear);
+    assert_pattern(&image);
+  }
+
+  #[test]
+  fn hdr_rle() {
+    let mut buf = b"#?RADIANCE\n\n+Y 1 +X 8\n".to_vec();
+    // A run of eight for red, then literal green, blue and exponent.
+    buf.extend_from_slice(&[2, 2, 0, 8, 136, 64]);
+    buf.extend_from_slice(&[8, 0, 1, 2, 3, 4, 5, 6, 7]);
+    buf.extend_from_slice(&[136, 0]);
+    buf.extend_from_slice(&[136, 136]);
+    let image = decode(&buf).unwrap();
+    assert_eq!((image.width, image.height), (8, 1));
+    assert_eq!(rgb(&image, 0, 0), [64.0, 0.0, 0.0]);
+    assert_eq!(rgb(&image, 7, 0), [64.0, 7.0, 0.0]);
+  }
+
+  #[test]
+  fn hdr_errors() {
+    assert!(decode(b"#?RADIANCE\n\n-Y 0 +X 0\n").is_err());
+    assert!(decode(b"#?RADIANCE\n\n+X 1 -Y 1\n\0\0\0\0").is_err());
+    assert!(decode(b"#?RADIANCE\nFORMAT=32-bit_rle_xyze\n\n-Y 1 +X 1\n\0\0\0\0").is_err());
+    assert!(decode(b"#?RADIANCE\n\n-Y 1 +X 1\n\0\0").is_err());
+    // A flat row far wider than the file.
+    assert!(decode(b"#?RADIANCE\n\n-Y 1 +X 1000000000000\n\0\0\0\0").is_err());
+  }
+}
\ No newline at end of file
@@ -0,0 +1,110 @@
+/**
+ * light.rs: Directional, point and spot light sources.
+ */
+
+use nalgebra::{Vector3, Matrix4};
+
+use crate::cli::{parse_f32, parse_vec3};
+
+#[derive(Debug, Clone, Copy)]
+pub enum Kind {
+  // Parallel rays travelling along `direction`.
+  Directional,
+  // Emits in all directions from `position`.
+  Point,
+  // Emits from `position` along `direction`, within a cone.
+  Spot,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Light {
+  pub kind: Kind,
+  pub position: Vector3<f32>,
+  pub direction: Vector3<f32>,
+  pub color: Vector3<f32>,
+  pub intensity: f32,
+
+  // Constant, linear and quadratic distance falloff (point and spot only).
+  pub attenuation: Vector3<f32>,
+
+  // Cosines of the inner (full intensity) and outer (zero intensity)
+  //   cone half-angles (spot only).
+  pub cone: (f32, f32),
+}
+
+impl Light {
+  pub fn new(kind: Kind) -> Light {
+    Light {
+      kind,
+      position: Vector3::zeros(),
+      direction: Vector3::new(-1.0, -1.0, -1.0).normalize(),
+      color: Vector3::new(1.0, 1.0, 1.0),
+      intensity: 1.0,
+      attenuation: Vector3::new(1.0, 0.0, 0.0),
+      cone: (15f32.to_radians().cos(), 25f32.to_radians().cos()),
+    }
+  }
+
+  // Parses a specification such as
+  //   "spot pos=0,2,2 dir=0,-1,-1 color=1,0.9,0.8 intensity=2 inner=10 outer=20".
+  pub fn parse(spec: &str) -> Result<Light, String> {
+    let mut words = spec.split_whitespace();
+    let mut light = Light::new(match words.next() {
+      Some("directional") | Some("dir") => Kind::Directional,
+      Some("point") => Kind::Point,
+      Some("spot") => Kind::Spot,
+      x => return Err(format!("Unrecognized light type: {}", x.unwrap_or(""))),
+    });
+
+    let (mut inner, mut outer) = (15f32, 25f32);
+    for word in words {
+      let (key, value) = word.split_once('=').ok_or(format!("Expected key=value: {}", word))?;
+      match key {
+        "pos" => light.position = parse_vec3(value)?,
+        "dir" => light.direction = parse_vec3(value)?.normalize(),
+        "color" => light.color = parse_vec3(value)?,
+        "intensity" => light.intensity = parse_f32(value)?,
+        "atten" => light.attenuation = parse_vec3(value)?,
+        "inner" => inner = parse_f32(value)?,
+        "outer" => outer = parse_f32(value)?,
+        x => return Err(format!("Unrecognized light property: {}", x)),
+      }
+    }
+    light.cone = (inner.to_radians().cos(), outer.max(inner).to_radians().cos());
+
+    Ok(light)
+  }
+
+  pub fn transform(&self, m: &Matrix4<f32>) -> Light {
+    Light {
+      position: (m * self.position.push(1.0)).xyz(),
+      direction: (m * self.direction.push(0.0)).xyz().normalize(),
+      ..*self
+    }
+  }
+
+  // Unit vector from `pos` towards the light, and the radiance arriving at `pos`.
+  pub fn incident(&self, pos: &Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+    let radiance = self.color * self.intensity;
+    if let Kind::Directional = self.kind {
+      return (-self.direction, radiance);
+    }
+
+    let to_light = self.position - pos;
+    let d = to_light.norm();
+    let l = to_light / d;
+    let falloff = 1.0 / Vector3::new(1.0, d, d * d).dot(&self.attenuation).max(1e-4);
+
+    let cone = match self.kind {
+      Kind::Spot => {
+        let (inner, outer) = self.cone;
+        let t = ((-l).dot(&self.direction) - outer) / (inner - outer).max(1e-4);
+        let t = t.clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
+      },
+      _ => 1.0,
+    };
+
+    (l, radiance * falloff * cone)
+  }
+}
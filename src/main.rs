@@ -6,42 +6,145 @@ mod model;
 mod vertex;
 mod shader;
 mod texture;
+mod image;
+mod color;
+mod environment;
+mod material;
+mod light;
+mod shadow;
+mod framebuffer;
+mod ssao;
+mod post;
+mod cell;
+mod palette;
 mod orbit;
 mod esc;
 mod ui;
+mod cli;
 
 use std::env;
 
+// Terminal cells are about twice as tall as they are wide.
+const CELL_ASPECT: f32 = 2.0;
+
 fn main() {
-  let (w, h) = match term_size::dimensions() {
-    Some((w, h)) => (w, h),
-    None => panic!("Unable to get terminal size"),
+  let args: Vec<String> = env::args().collect();
+  let opts = match cli::Options::parse(&args) {
+    Ok(Some(opts)) => opts,
+    Ok(None) => {
+      eprintln!("{}", cli::USAGE);
+      return;
+    },
+    Err(e) => {
+      eprintln!("{}\n\n{}", e, cli::USAGE);
+      return;
+    },
+  };
+
+  // Frames leave out the first row and column of cells.
+  let (w, h) = match (opts.size, term_size::dimensions()) {
+    (Some((w, h)), _) => (w + 1, h + 1),
+    (None, Some((w, h))) => (w, h),
+    (None, None) => {
+      eprintln!("Unable to get terminal size; use --size to print a single frame");
+      return;
+    },
   };
+  // Rendered pixels, several of which may share a cell.
+  let (cw, ch) = opts.glyphs.pixels();
+  let (w, h) = (w * cw, h * ch);
   let width = w as f32;
   let height = h as f32;
 
-  let args: Vec<String> = env::args().collect();
-  if args.len() < 2 || args[1] == "--help" || args[1] == "-h" {
-    eprintln!("Usage: raster [model.obj] {{diffuse.tga}} {{normal.tga}} {{specular.tga}}");
-    return;
-  }
+  let mut obj = model::Model::load_obj(&opts.model).unwrap();
+
+  // Command-line material options take precedence over the model's MTL.
+  let mtl = obj.material();
+  let mut material = match &mtl {
+    Some(m) => m.material,
+    None => material::Material::new(),
+  };
+  opts.material.apply(&mut material);
 
-  let mut obj = model::Model::load_obj(&args[1]).unwrap();
   let mut shader = shader::Shader::new(w, h, &mut obj);
 
-  shader.set_light(1.0, 1.0, 1.0);
-  shader.set_viewport(width / 8.0, height / 8.0, width * 0.75, height * 0.75);
+  shader.set_shadow_size(opts.shadow_size);
+  shader.set_ssao(opts.ssao);
+  shader.set_mode(opts.mode);
+  shader.set_toon_bands(opts.toon_bands);
+  shader.set_outline_color(opts.outline_color);
+  shader.set_tonemap(opts.tonemap);
+  shader.set_exposure(opts.exposure);
+  shader.set_background(opts.background);
+  shader.set_parallax(opts.parallax_scale, opts.parallax_steps);
+  shader.set_fog(opts.fog);
+  shader.set_supersampling(opts.ssaa, opts.ssaa_filter);
+  shader.set_fog_range(opts.fog_range.0, opts.fog_range.1);
+  shader.set_fog_density(opts.fog_density);
+  if let Some(path) = &opts.env {
+    let mut env = environment::Environment::load(path);
+    env.intensity = opts.env_intensity;
+    shader.set_environment(env);
+  }
+  shader.set_shading(opts.shading);
+  shader.set_wireframe(opts.wireframe);
+  if opts.lights.is_empty() {
+    shader.add_light(light::Light::new(light::Kind::Directional));
+  }
+  for l in &opts.lights {
+    shader.add_light(*l);
+  }
+  shader.set_material(material);
+  shader.set_normal_space(opts.normal_space);
+  shader.set_glyphs(opts.glyphs);
+  shader.set_palette(opts.colors.unwrap_or_else(palette::Palette::detect), opts.dither);
+
+  // Fits a square viewport over 3/4 of the screen, given the pixels' height
+  //   to width ratio.
+  let aspect = CELL_ASPECT * cw as f32 / ch as f32;
+  let size = width.min(height * aspect) * 0.75;
+  shader.set_viewport((width - size) / 2.0, (height - size / aspect) / 2.0, size, size / aspect);
   shader.set_projection(20.0);
 
-  if args.len() > 2 {
-    shader.set_diffuse(&args[2]);
+  let maps = [
+    ("diffuse", &opts.diffuse),
+    ("normal", &opts.normal),
+    ("specular", &opts.specular),
+    ("metallic", &opts.metallic),
+    ("roughness", &opts.roughness),
+    ("ao", &opts.ao),
+    ("emissive", &opts.emissive),
+    ("height", &opts.height),
+    ("matcap", &opts.matcap),
+  ];
+  for (slot, path) in maps {
+    match (path, mtl.as_ref().and_then(|m| m.map(slot))) {
+      (Some(path), _) => shader.load_map(slot, path),
+      (None, Some(map)) => {
+        shader.load_map(slot, &map.path);
+        if map.clamp {
+          shader.set_wrap(Some(slot), texture::Wrap::ClampToEdge).unwrap();
+        }
+      },
+      _ => (),
+    }
   }
-  if args.len() > 3 {
-    shader.set_normal(&args[3]);
+
+  for (map, filter) in &opts.filters {
+    if let Err(e) = shader.set_filter(map.as_deref(), *filter) {
+      eprintln!("{}", e);
+      return;
+    }
   }
-  if args.len() > 4 {
-    shader.set_specular(&args[4]);
+  for (map, wrap) in &opts.wraps {
+    if let Err(e) = shader.set_wrap(map.as_deref(), *wrap) {
+      eprintln!("{}", e);
+      return;
+    }
   }
 
-  ui::UI::new(&mut shader).run();
+  match opts.size {
+    Some(_) => orbit::Orbit::new(&mut shader).print(),
+    None => ui::UI::new(&mut shader).run(),
+  }
 }
@@ -0,0 +1,251 @@
+/**
+ * material.rs: Surface material parameters.
+ */
+
+use std::error::Error;
+use std::f32::consts::PI;
+use std::fs;
+use std::path::Path;
+
+use nalgebra::{Vector3, Vector4};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Brdf {
+  BlinnPhong,
+  // Metallic/roughness GGX microfacet model.
+  CookTorrance,
+}
+
+// How the diffuse map's alpha and the material's opacity are applied.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Alpha {
+  Opaque,
+  // Cutout: fragments below the cutoff are discarded, the rest are opaque.
+  Mask,
+  // Translucent fragments are sorted by depth and blended.
+  Blend,
+}
+
+// Surface description.  Colors are linear multipliers in [0, 1]; the
+//   diffuse color tints the diffuse map (if any) and doubles as the base
+//   color for Cook-Torrance.  Specular color and shininess only apply to
+//   Blinn-Phong, metallic through emissive only to Cook-Torrance.  Opacity
+//   multiplies the diffuse map's alpha.
+#[derive(Debug, Clone, Copy)]
+pub struct Material {
+  pub brdf: Brdf,
+  pub ambient: Vector3<f32>,
+  pub diffuse: Vector3<f32>,
+  pub specular: Vector3<f32>,
+  pub shininess: f32,
+  pub metallic: f32,
+  pub roughness: f32,
+  pub ao: f32,
+  pub emissive: Vector3<f32>,
+  pub opacity: f32,
+  pub alpha: Alpha,
+  pub alpha_cutoff: f32,
+}
+
+impl Material {
+  pub fn new() -> Material {
+    Material {
+      brdf: Brdf::BlinnPhong,
+      ambient: Vector3::new(0.1, 0.1, 0.1),
+      diffuse: Vector3::new(1.0, 1.0, 1.0),
+      specular: Vector3::new(0.5, 0.5, 0.5),
+      shininess: 32.0,
+      metallic: 0.0,
+      roughness: 0.5,
+      ao: 1.0,
+      emissive: Vector3::zeros(),
+      opacity: 1.0,
+      alpha: Alpha::Opaque,
+      alpha_cutoff: 0.5,
+    }
+  }
+}
+
+// Texture map referenced by an MTL file.
+#[derive(Debug, Clone)]
+pub struct MapRef {
+  pub path: String,
+  pub clamp: bool,
+}
+
+// A named MTL material and its texture maps, keyed by shader slot
+//   ("diffuse", "normal", ...).
+#[derive(Debug, Clone)]
+pub struct MtlMaterial {
+  pub name: String,
+  pub material: Material,
+  pub maps: Vec<(&'static str, MapRef)>,
+}
+
+impl MtlMaterial {
+  pub fn map(&self, slot: &str) -> Option<&MapRef> {
+    self.maps.iter().find(|(s, _)| *s == slot).map(|(_, m)| m)
+  }
+}
+
+// Parses an MTL file.  Ka is ignored, since exporters commonly write 1.0
+//   there, which would wash out the scene; use --ambient instead.
+pub fn load_mtl(path: &Path) -> Result<Vec<MtlMaterial>, Box<dyn Error>> {
+  let dir = path.parent().unwrap_or(Path::new(""));
+  let mut out: Vec<MtlMaterial> = vec![];
+
+  for line in fs::read_to_string(path)?.lines() {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let (cmd, args) = match words.split_first() {
+      Some((cmd, args)) => (*cmd, args),
+      None => continue,
+    };
+
+    if cmd == "newmtl" {
+      out.push(MtlMaterial {
+        name: args.join(" "),
+        material: Material::new(),
+        maps: vec![],
+      });
+      continue;
+    }
+    let current = match out.last_mut() {
+      Some(x) => x,
+      None => continue,
+    };
+
+    let num = |i: usize| args.get(i).and_then(|x| x.parse::<f32>().ok());
+    let color = || Some(Vector3::new(num(0)?, num(1).or(num(0))?, num(2).or(num(0))?));
+    match cmd {
+      "Kd" => current.material.diffuse = color().ok_or("Invalid Kd")?,
+      "Ks" => current.material.specular = color().ok_or("Invalid Ks")?,
+      "Ke" => current.material.emissive = color().ok_or("Invalid Ke")?,
+      "Ns" => current.material.shininess = num(0).ok_or("Invalid Ns")?,
+      "Pr" => current.material.roughness = num(0).ok_or("Invalid Pr")?,
+      "Pm" => current.material.metallic = num(0).ok_or("Invalid Pm")?,
+      "d" | "Tr" => {
+        let value = num(0).ok_or(format!("Invalid {}", cmd))?;
+        current.material.opacity = match cmd {
+          "d" => value,
+          _ => 1.0 - value,
+        };
+        if current.material.opacity < 1.0 {
+          current.material.alpha = Alpha::Blend;
+        }
+      },
+      "map_Kd" | "map_Ks" | "map_Ns" | "map_Ke" | "map_Pr" | "map_Pm" | "map_Bump" | "map_bump" | "bump" | "norm" | "disp" => {
+        let slot = match cmd {
+          "map_Kd" => "diffuse",
+          "map_Ks" | "map_Ns" => "specular",
+          "map_Ke" => "emissive",
+          "map_Pr" => "roughness",
+          "map_Pm" => "metallic",
+          "disp" => "height",
+          _ => "normal",
+        };
+        if let Some(map) = parse_map(args, dir) {
+          current.maps.push((slot, map));
+        }
+      },
+      "#" | "Ka" | "Tf" | "Ni" | "illum" => (),
+      x if x.starts_with('#') => (),
+      x => eprintln!("Unrecognized MTL command: {}", x),
+    }
+  }
+
+  Ok(out)
+}
+
+// Parses "[-option args...] filename", keeping only -clamp.
+fn parse_map(args: &[&str], dir: &Path) -> Option<MapRef> {
+  let mut clamp = false;
+  let mut i = 0;
+  while i + 1 < args.len() && args[i].starts_with('-') {
+    let option = args[i];
+    i += 1;
+    match option {
+      "-clamp" => {
+        clamp = args[i] == "on";
+        i += 1;
+      },
+      // Up to three numeric arguments
+      "-o" | "-s" | "-t" => {
+        let start = i;
+        while i + 1 < args.len() && i < start + 3 && args[i].parse::<f32>().is_ok() {
+          i += 1;
+        }
+      },
+      "-mm" => i += 2,
+      _ => i += 1,
+    }
+  }
+
+  let file = args.get(i..)?.join(" ");
+  match file.is_empty() {
+    true => None,
+    false => Some(MapRef {
+      path: dir.join(file).to_string_lossy().into_owned(),
+      clamp,
+    }),
+  }
+}
+
+// Cook-Torrance with the GGX distribution, Schlick-GGX geometry and Schlick
+//   Fresnel terms, returning reflected light for unit incoming irradiance.
+//   Scaled by pi so that a rough dielectric matches the Lambert term used by
+//   Blinn-Phong for the same light.
+pub fn cook_torrance(
+  n: &Vector3<f32>,
+  v: &Vector3<f32>,
+  l: &Vector3<f32>,
+  albedo: &Vector3<f32>,
+  metallic: f32,
+  roughness: f32
+) -> Vector3<f32> {
+  let h = (v + l).normalize();
+  let n_dot_l = n.dot(l).max(0.0);
+  let n_dot_v = n.dot(v).max(1e-4);
+  let n_dot_h = n.dot(&h).max(0.0);
+  let v_dot_h = v.dot(&h).max(0.0);
+
+  let a = (roughness * roughness).max(1e-3);
+  let a2 = a * a;
+  let d = a2 / (PI * (n_dot_h * n_dot_h * (a2 - 1.0) + 1.0).powi(2));
+
+  let k = (roughness + 1.0).powi(2) / 8.0;
+  let g = (n_dot_v / (n_dot_v * (1.0 - k) + k)) * (n_dot_l / (n_dot_l * (1.0 - k) + k));
+
+  let f0 = Vector3::new(0.04, 0.04, 0.04).lerp(albedo, metallic);
+  let f = f0 + (Vector3::new(1.0, 1.0, 1.0) - f0) * (1.0 - v_dot_h).powi(5);
+
+  let specular = f * (d * g / (4.0 * n_dot_v * n_dot_l.max(1e-4)));
+  let kd = (Vector3::new(1.0, 1.0, 1.0) - f) * (1.0 - metallic);
+
+  (kd.component_mul(albedo) + specular * PI) * n_dot_l
+}
+
+// Cook-Torrance under image-based lighting, with the split-sum
+//   approximation: `irradiance` feeds the diffuse term (already divided by
+//   pi, as Lambert is above) and `radiance` is the environment along the
+//   mirror direction, blurred for `roughness`.  The specular BRDF's integral
+//   uses Karis' analytic fit.
+pub fn cook_torrance_ibl(
+  n: &Vector3<f32>,
+  v: &Vector3<f32>,
+  albedo: &Vector3<f32>,
+  metallic: f32,
+  roughness: f32,
+  irradiance: &Vector3<f32>,
+  radiance: &Vector3<f32>
+) -> Vector3<f32> {
+  let n_dot_v = n.dot(v).max(1e-4);
+  let r = Vector4::new(-1.0, -0.0275, -0.572, 0.022) * roughness + Vector4::new(1.0, 0.0425, 1.04, -0.04);
+  let a004 = (r.x * r.x).min((-9.28 * n_dot_v).exp2()) * r.x + r.y;
+  let (scale, bias) = (a004 * -1.04 + r.z, a004 * 1.04 + r.w);
+
+  let f0 = Vector3::new(0.04, 0.04, 0.04).lerp(albedo, metallic);
+  let specular = (f0 * scale).add_scalar(bias);
+  let kd = (Vector3::new(1.0, 1.0, 1.0) - specular) * (1.0 - metallic);
+
+  kd.component_mul(albedo).component_mul(irradiance) + specular.component_mul(radiance)
+}
\ No newline at end of file
@@ -5,10 +5,12 @@
 use std::error::Error;
 use std::fs::File;
 use std::io::{self, BufRead};
-use std::{vec, path::Path};
+use std::{vec, path::{Path, PathBuf}};
 
 use nalgebra::{Vector2, Vector3, Vector4};
 
+use crate::material::{self, MtlMaterial};
+
 fn read_lines<P>(filename: P) -> io::Result<io::Lines<io::BufReader<File>>>
 where P: AsRef<Path>, {
   let file = File::open(filename)?;
@@ -27,6 +29,9 @@ pub struct Model {
 
   bbox: [f32; 6],
 
+  mtllib: Vec<PathBuf>,
+  usemtl: Option<String>,
+
   pub center: Vector3<f32>,
 }
 
@@ -47,6 +52,9 @@ impl Model {
         std::f32::MAX, std::f32::MIN,
       ],
 
+      mtllib: vec![],
+      usemtl: None,
+
       center: Vector3::zeros(),
     }
   }
@@ -120,7 +128,19 @@ impl Model {
           } else if cmd == "vn" {
             out.normal_buffer.push(Vector3::from_vec(tmp).normalize());
           } else if cmd == "vt" {
-            out.uv_buffer.push(Vector2::new(tmp[0], 1.0 - tmp[1]));
+            out.uv_buffer.push(Vector2::new(tmp[0], tmp[1]));
+          }
+        },
+        "mt" | "us" => {
+          let (cmd, arg) = ip.split_once(' ').unwrap_or((&ip, ""));
+          match cmd {
+            "mtllib" => out.mtllib.push(path.parent().unwrap_or(Path::new("")).join(arg.trim())),
+            "usemtl" => match &out.usemtl {
+              None => out.usemtl = Some(arg.trim().to_string()),
+              Some(x) if x != arg.trim() => eprintln!("Only the first material is used, ignoring: {}", arg.trim()),
+              _ => (),
+            },
+            x => eprintln!("Unrecognized OBJ command: {}", x),
           }
         },
         "# " => (),
@@ -142,6 +162,32 @@ impl Model {
     Ok(out)
   }
 
+  // The material selected by the first `usemtl`, or the first one defined
+  //   if there is none.
+  pub fn material(&self) -> Option<MtlMaterial> {
+    let mut all = vec![];
+    for lib in &self.mtllib {
+      match material::load_mtl(lib) {
+        Ok(x) => all.extend(x),
+        Err(e) => eprintln!("Unable to load {}: {}", lib.display(), e),
+      }
+    }
+
+    match &self.usemtl {
+      Some(name) => all.into_iter().find(|m| m.name == *name),
+      None => all.into_iter().next(),
+    }
+  }
+
+  // Radius of the sphere around `center` enclosing the bounding box.
+  pub fn radius(&self) -> f32 {
+    Vector3::new(
+      self.bbox[1] - self.bbox[0],
+      self.bbox[3] - self.bbox[2],
+      self.bbox[5] - self.bbox[4],
+    ).norm() / 2.0
+  }
+
   pub fn nfaces(&self) -> usize {
     self.face_vert.len() / 3
   }
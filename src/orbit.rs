@@ -16,12 +16,13 @@ pub struct Orbit<'a> {
   spherical: Vector2<f32>,
   sph_delta: Vector2<f32>,
   dist_delta: f32,
+  dirty: bool,
   pub distance: f32,
   pub rotate_start: Vector2<f32>,
 }
 
-impl Orbit<'_> {
-  pub fn new<'a>(s: &'a mut Shader<'a>) -> Orbit<'a> {
+impl<'a> Orbit<'a> {
+  pub fn new(s: &'a mut Shader<'a>) -> Orbit<'a> {
     Orbit {
       shader: s,
 
@@ -29,6 +30,7 @@ impl Orbit<'_> {
       spherical: Vector2::zeros(),
       sph_delta: Vector2::new(FUDGE, 0.0),
       dist_delta: 0.0,
+      dirty: false,
       distance: 20.0,
       rotate_start: Vector2::zeros(),
     }
@@ -38,22 +40,40 @@ impl Orbit<'_> {
     let rotate_end = Vector2::new(x, y);
     let delta = rotate_end - self.rotate_start;
 
+    // Mouse positions are in cells.
+    let rows = (self.shader.height / self.shader.cell_size().1) as f32;
     self.sph_delta -= Vector2::new(
-      (2.0 * std::f32::consts::PI * delta.x) / (self.shader.height as f32),
-      (2.0 * std::f32::consts::PI * delta.y) / (self.shader.height as f32)
+      (2.0 * std::f32::consts::PI * delta.x) / rows,
+      (2.0 * std::f32::consts::PI * delta.y) / rows
     );
 
     self.rotate_start = rotate_end;
   }
 
+  // Prints a single frame from the starting position.
+  pub fn print(&mut self) {
+    self.shader.look_at(&(Vector3::z() * self.distance));
+    self.shader.print();
+  }
+
   pub fn zoom(&mut self, amt: f32) {
     self.dist_delta = amt;
   }
+
+  pub fn shader(&mut self) -> &mut Shader<'a> {
+    self.shader
+  }
+
+  // Forces a render on the next update, e.g. after changing shader settings.
+  pub fn redraw(&mut self) {
+    self.dirty = true;
+  }
   
   pub fn update(&mut self) {
-    if self.sph_delta.metric_distance(&Vector2::zeros()) < FUDGE && self.dist_delta.abs() < FUDGE {
+    if self.sph_delta.metric_distance(&Vector2::zeros()) < FUDGE && self.dist_delta.abs() < FUDGE && !self.dirty {
       return;
     }
+    self.dirty = false;
 
     if (self.dist_delta < FUDGE && self.distance > 1.0) || (self.dist_delta > -FUDGE && self.distance < 1000.0) {
       self.distance += self.dist_delta;
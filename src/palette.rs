@@ -0,0 +1,165 @@
+/**
+ * palette.rs: Terminal color palettes, with dithering for reduced ones.
+ */
+
+use std::env;
+
+use nalgebra::Vector3;
+
+// Channel levels of the xterm 256-color 6x6x6 cube (indices 16-231).
+const CUBE: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+// The 16 basic colors, as xterm draws them.
+const ANSI16: [[u8; 3]; 16] = [
+  [0, 0, 0], [205, 0, 0], [0, 205, 0], [205, 205, 0], [0, 0, 238], [205, 0, 205], [0, 205, 205], [229, 229, 229],
+  [127, 127, 127], [255, 0, 0], [0, 255, 0], [255, 255, 0], [92, 92, 255], [255, 0, 255], [0, 255, 255], [255, 255, 255],
+];
+
+// 4x4 Bayer threshold matrix for ordered dithering.
+const BAYER: [[u8; 4]; 4] = [
+  [0, 8, 2, 10],
+  [12, 4, 14, 6],
+  [3, 11, 1, 9],
+  [15, 7, 13, 5],
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Palette {
+  // 24-bit color.
+  TrueColor,
+  // The xterm color cube and gray ramp, leaving out the themable first 16.
+  Ansi256,
+  Ansi16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dither {
+  // Each pixel takes the nearest palette color.
+  None,
+  // A fixed Bayer pattern, stable while the model moves.
+  Ordered,
+  // Floyd-Steinberg error diffusion.
+  Diffusion,
+}
+
+impl Palette {
+  // Guesses the terminal's palette from COLORTERM and TERM.
+  pub fn detect() -> Palette {
+    let colorterm = env::var("COLORTERM").unwrap_or_default();
+    let term = env::var("TERM").unwrap_or_default();
+    match (colorterm.as_str(), term.as_str()) {
+      ("truecolor" | "24bit", _) => Palette::TrueColor,
+      (_, t) if t.ends_with("-direct") => Palette::TrueColor,
+      (_, t) if t.contains("256color") => Palette::Ansi256,
+      _ => Palette::Ansi16,
+    }
+  }
+
+  // Index and value of the palette color closest to `c`.
+  fn nearest(&self, c: &Vector3<u8>) -> (usize, Vector3<u8>) {
+    let distance = |p: &Vector3<u8>| (0..3).map(|i| (c[i] as i32 - p[i] as i32).pow(2)).sum::<i32>();
+    match self {
+      Palette::TrueColor => (0, *c),
+      Palette::Ansi256 => {
+        let level = |x: u8| (0..6).min_by_key(|&i| (CUBE[i] as i32 - x as i32).abs()).unwrap();
+        let (r, g, b) = (level(c.x), level(c.y), level(c.z));
+        let cube = Vector3::new(CUBE[r], CUBE[g], CUBE[b]);
+
+        // Grays run from 8 to 238 in steps of 10.
+        let mean = (c.x as i32 + c.y as i32 + c.z as i32) / 3;
+        let step = ((mean - 8 + 5) / 10).clamp(0, 23);
+        let gray = Vector3::repeat((8 + step * 10) as u8);
+
+        match distance(&gray) < distance(&cube) {
+          true => (232 + step as usize, gray),
+          false => (16 + 36 * r + 6 * g + b, cube),
+        }
+      },
+      Palette::Ansi16 => {
+        let i = (0..16).min_by_key(|&i| distance(&Vector3::from(ANSI16[i]))).unwrap();
+        (i, Vector3::from(ANSI16[i]))
+      },
+    }
+  }
+
+  // Typical spacing between neighboring palette levels, spread by dithering.
+  fn spacing(&self) -> f32 {
+    match self {
+      Palette::TrueColor => 1.0,
+      Palette::Ansi256 => 32.0,
+      Palette::Ansi16 => 128.0,
+    }
+  }
+
+  // SGR parameters setting `c` as the foreground or background color.
+  pub fn sgr(&self, c: &Vector3<u8>, background: bool) -> String {
+    let (index, _) = self.nearest(c);
+    match (self, background) {
+      (Palette::TrueColor, false) => format!("38;2;{};{};{}", c.x, c.y, c.z),
+      (Palette::TrueColor, true) => format!("48;2;{};{};{}", c.x, c.y, c.z),
+      (Palette::Ansi256, false) => format!("38;5;{}", index),
+      (Palette::Ansi256, true) => format!("48;5;{}", index),
+      (Palette::Ansi16, _) => {
+        let base = match (index < 8, background) {
+          (true, false) => 30,
+          (true, true) => 40,
+          (false, false) => 90 - 8,
+          (false, true) => 100 - 8,
+        };
+        (base + index).to_string()
+      },
+    }
+  }
+
+  // Replaces covered pixels, `width` to a row, with palette colors.
+  pub fn quantize(&self, pixels: &mut [Option<Vector3<u8>>], width: usize, dither: Dither) {
+    if *self == Palette::TrueColor {
+      return;
+    }
+
+    let clamp = |c: Vector3<f32>| c.map(|x| x.round().clamp(0.0, 255.0) as u8);
+    match dither {
+      Dither::None => {
+        for c in pixels.iter_mut().flatten() {
+          *c = self.nearest(c).1;
+        }
+      },
+      Dither::Ordered => {
+        for (idx, p) in pixels.iter_mut().enumerate() {
+          if let Some(c) = p {
+            let threshold = (BAYER[idx / width % 4][idx % width % 4] as f32 + 0.5) / 16.0 - 0.5;
+            *c = self.nearest(&clamp(c.cast::<f32>().add_scalar(threshold * self.spacing()))).1;
+          }
+        }
+      },
+      Dither::Diffusion => {
+        // Error pushed onto uncovered pixels is dropped.
+        let mut error = vec![Vector3::<f32>::zeros(); pixels.len()];
+        for idx in 0..pixels.len() {
+          let c = match &mut pixels[idx] {
+            Some(c) => c,
+            None => continue,
+          };
+          let wanted = c.cast::<f32>() + error[idx];
+          *c = self.nearest(&clamp(wanted)).1;
+          let e = wanted - c.cast::<f32>();
+
+          let x = idx % width;
+          let mut spread = |i: usize, weight: f32| {
+            if let Some(err) = error.get_mut(i) {
+              *err += e * weight;
+            }
+          };
+          if x + 1 < width {
+            spread(idx + 1, 7.0 / 16.0);
+            spread(idx + width + 1, 1.0 / 16.0);
+          }
+          if x > 0 {
+            spread(idx + width - 1, 3.0 / 16.0);
+          }
+          spread(idx + width, 5.0 / 16.0);
+        }
+      },
+    }
+  }
+}
\ No newline at end of file
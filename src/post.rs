@@ -0,0 +1,85 @@
+/**
+ * post.rs: Screen-space post-processing passes.
+ */
+
+use nalgebra::{Vector3, Vector4};
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+
+use crate::framebuffer::Framebuffer;
+
+// Paints `color` over covered pixels on a silhouette, a depth discontinuity
+//   larger than `depth_step` (view-space units) or a crease whose normals
+//   differ by more than `crease` (cosine).  Only the nearer side of each
+//   discontinuity is painted, keeping lines `width` pixels wide.
+pub fn outline(fb: &mut Framebuffer, color: Vector4<f32>, depth_step: f32, crease: f32, width: i32) {
+  let (w, h) = (fb.width, fb.height);
+  let edges: Vec<bool> = (0..fb.fragments.len()).into_par_iter().map(|idx| {
+    let depth = match fb.depth(idx) {
+      Some(d) => d,
+      None => return false,
+    };
+    let (x, y) = (idx as i32 % w, idx as i32 / w);
+
+    let offsets = [(-1, 0), (1, 0), (0, -1), (0, 1)].iter().flat_map(|(dx, dy)| (1..=width).map(move |k| (dx * k, dy * k)));
+    offsets.into_iter().any(|(dx, dy)| {
+      let (nx, ny) = (x + dx, y + dy);
+      if nx < 0 || ny < 0 || nx >= w || ny >= h {
+        return false;
+      }
+
+      let n = (nx + ny * w) as usize;
+      match fb.depth(n) {
+        None => true,
+        Some(d) if d - depth > depth_step => true,
+        Some(d) => fb.normal[idx].dot(&fb.normal[n]) < crease && (depth, idx) < (d, n),
+      }
+    })
+  }).collect();
+
+  for (idx, edge) in edges.into_iter().enumerate() {
+    if edge {
+      fb.color[idx] = color;
+    }
+  }
+}
+
+// Filter combining each output pixel's samples when supersampling.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Downsample {
+  // Equal weights over the pixel's N×N samples.
+  Box,
+  // Weights falling off linearly to the neighboring pixels' centers,
+  //   trading some sharpness for smoother edges.
+  Tent,
+}
+
+// Reduces `colors`, rendered at `n` times the `width` by `height` output in
+//   each direction, to the output resolution.  Alpha is coverage: uncovered
+//   samples count as black, and only fully uncovered pixels stay empty.
+pub fn downsample(colors: &[Vector4<f32>], width: i32, height: i32, n: i32, filter: Downsample) -> Vec<Vector4<f32>> {
+  // Samples along one axis contributing to output pixel `i`, with weights.
+  let taps = |i: i32, size: i32| -> Vec<(i32, f32)> {
+    let center = (i * n) as f32 + (n - 1) as f32 / 2.0;
+    (i * n - n..i * n + 2 * n).filter(|s| (0..size).contains(s)).map(|s| (s, match filter {
+      Downsample::Box => (s.div_euclid(n) == i) as i32 as f32,
+      Downsample::Tent => (1.0 - (s as f32 - center).abs() / n as f32).max(0.0),
+    })).filter(|(_, k)| *k > 0.0).collect()
+  };
+
+  (0..width * height).into_par_iter().map(|idx| {
+    let (x, y) = (idx % width, idx / width);
+    let (mut sum, mut coverage, mut total) = (Vector3::zeros(), 0.0, 0.0);
+    for (sy, ky) in taps(y, height * n) {
+      for (sx, kx) in taps(x, width * n) {
+        let c = colors[(sx + sy * width * n) as usize];
+        sum += c.xyz() * c.w * kx * ky;
+        coverage += c.w * kx * ky;
+        total += kx * ky;
+      }
+    }
+    match coverage {
+      0.0 => Vector4::zeros(),
+      _ => (sum / coverage).push(coverage / total),
+    }
+  }).collect()
+}
\ No newline at end of file
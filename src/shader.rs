@@ -4,18 +4,105 @@
  * Responsible for printing the output escape sequences to the screen.
  */
 
-use std::collections::HashMap;
 use std::io::{self, Write};
 
 use crate::model::Model;
 use crate::vertex::Vertex;
-use crate::texture::Texture;
-
-use nalgebra::{Vector3, Vector4, Matrix3, Matrix4};
-use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use crate::texture::{Filter, Texture, Wrap};
+use crate::material::{self, Alpha, Brdf, Material};
+use crate::light::{self, Light};
+use crate::shadow::ShadowMap;
+use crate::framebuffer::{Framebuffer, Fragment};
+use crate::ssao;
+use crate::post::{self, Downsample};
+use crate::color::{self, Tonemap};
+use crate::environment::Environment;
+use crate::cell::Glyphs;
+use crate::palette::{Dither, Palette};
+
+use nalgebra::{Vector2, Vector3, Vector4, Matrix3, Matrix4};
+use rayon::prelude::{IntoParallelIterator, IntoParallelRefIterator, IntoParallelRefMutIterator, IndexedParallelIterator, ParallelIterator};
 
 const ONES: Vector4<f32> = Vector4::new(1.0, 1.0, 1.0, 1.0);
 
+// Toon outline thresholds: depth jump (as a fraction of the model's size)
+//   and crease angle (cosine between neighboring normals).
+const OUTLINE_DEPTH: f32 = 0.2;
+const OUTLINE_CREASE: f32 = 0.5;
+
+// Depth tolerance for hidden-line edges, as a fraction of the model's size.
+const WIREFRAME_BIAS: f32 = 0.05;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shading {
+  // One normal per face, lit at its centroid.
+  Flat,
+  // Lit at each vertex, with the lighting interpolated across the face and
+  //   modulated by the diffuse map per pixel.
+  Gouraud,
+  // Normals interpolated and lit per pixel, with normal mapping.
+  Phong,
+}
+
+// Coordinates in which a normal map's texels are given.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NormalSpace {
+  // Relative to the surface, along its UV directions (mostly blue maps).
+  Tangent,
+  // The model's own coordinates.
+  Object,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Wireframe {
+  Off,
+  // Every triangle edge, front or back.
+  All,
+  // Only edges not hidden behind a nearer surface.
+  HiddenLine,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+  Shaded,
+  // Quantized diffuse bands, hard highlights and outlines.
+  Toon,
+  // Unlit, looking up a lit-sphere texture by view-space normal.
+  Matcap,
+
+  // Debug visualizations, replacing lighting entirely.
+  Normals,
+  WorldNormals,
+  Depth,
+  Uv,
+  Tangents,
+  Bitangents,
+  Overdraw,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Background {
+  // Left to the terminal.
+  None,
+  // The environment map, as seen by the camera.
+  Environment,
+  // A flat color (linear).
+  Color(Vector3<f32>),
+}
+
+// Blends surfaces toward the background color with depth, measured across
+//   the model's bounding sphere from its front (0) to its back (1).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fog {
+  Off,
+  // Ramps from none at the start of the range to full at its end.
+  Linear,
+  // 1 - e^(-density * depth).
+  Exponential,
+  // 1 - e^(-(density * depth)^2), clearer up front.
+  ExponentialSquared,
+}
+
 #[derive(Debug)]
 pub struct Shader<'a> {
   pub width: i32,
@@ -23,16 +110,50 @@ pub struct Shader<'a> {
 
   model: &'a mut Model,
 
-  light: Vector4<f32>,
-  uniform_light: Vector3<f32>,
+  lights: Vec<Light>,
+  uniform_lights: Vec<Light>,
+  shadows: Vec<Option<ShadowMap>>,
+  shadow_size: usize,
+  ssao_radius: f32,
+  mode: Mode,
+  toon_bands: u32,
+  shading: Shading,
+  wireframe: Wireframe,
+  outline_color: Vector3<f32>,
+  material: Material,
+  tonemap: Tonemap,
+  // In stops.
+  exposure: f32,
+  environment: Option<Environment>,
+  background: Background,
+  fog: Fog,
+  fog_range: (f32, f32),
+  fog_density: f32,
+  supersampling: u32,
+  downsample: Downsample,
+  glyphs: Glyphs,
+  palette: Palette,
+  dither: Dither,
 
   pub model_view: Matrix4<f32>,
+  model_view_inv: Matrix4<f32>,
+  model_view_it: Matrix4<f32>,
   pub projection: Matrix4<f32>,
   pub viewport: Matrix4<f32>,
 
   diffuse_map: Texture,
   normal_map: Texture,
+  normal_space: NormalSpace,
   specular_map: Texture,
+  metallic_map: Texture,
+  roughness_map: Texture,
+  ao_map: Texture,
+  emissive_map: Texture,
+  height_map: Texture,
+  // Depth of the height map's range, in UV units.
+  parallax_scale: f32,
+  parallax_steps: u32,
+  matcap_map: Texture,
 }
 
 impl Shader<'_> {
@@ -43,16 +164,53 @@ impl Shader<'_> {
 
       model: m,
 
-      light: Vector4::identity(),
-      uniform_light: Vector3::identity(),
+      lights: vec![],
+      uniform_lights: vec![],
+      shadows: vec![],
+      shadow_size: 256,
+      ssao_radius: 0.0,
+      mode: Mode::Shaded,
+      toon_bands: 4,
+      shading: Shading::Phong,
+      wireframe: Wireframe::Off,
+      outline_color: Vector3::zeros(),
+      material: Material::new(),
+      tonemap: Tonemap::Clamp,
+      exposure: 0.0,
+      environment: None,
+      background: Background::None,
+      fog: Fog::Off,
+      fog_range: (0.0, 1.0),
+      fog_density: 2.0,
+      supersampling: 1,
+      downsample: Downsample::Box,
+      glyphs: Glyphs::Space,
+      palette: Palette::TrueColor,
+      dither: Dither::None,
 
       model_view: Matrix4::identity(),
+      model_view_inv: Matrix4::identity(),
+      model_view_it: Matrix4::identity(),
       projection: Matrix4::identity(),
       viewport: Matrix4::identity(),
 
-      diffuse_map: Texture::new(),
+      diffuse_map: Texture::srgb(),
       normal_map: Texture::new(),
+      normal_space: NormalSpace::Tangent,
       specular_map: Texture::new(),
+      metallic_map: Texture::new(),
+      roughness_map: Texture::new(),
+      ao_map: Texture::new(),
+      emissive_map: Texture::srgb(),
+      height_map: Texture::new(),
+      parallax_scale: 0.05,
+      parallax_steps: 16,
+      matcap_map: {
+        // Normals never leave the sphere, so edge texels shouldn't wrap.
+        let mut t = Texture::srgb();
+        t.wrap = [Wrap::ClampToEdge; 2];
+        t
+      },
     }
   }
 
@@ -62,7 +220,7 @@ impl Shader<'_> {
     }
 
     if let Some(x) = self.model.normal(iface, nthvert) {
-      let prod = self.model_view.try_inverse().unwrap().transpose() * Vector4::new(x.x, x.y, x.z, 0.0);
+      let prod = self.model_view_it * Vector4::new(x.x, x.y, x.z, 0.0);
       vert.varying_normal.set_column(nthvert, &prod.xyz());
     }
 
@@ -71,59 +229,293 @@ impl Shader<'_> {
     self.projection * out
   }
 
-  pub fn fragment(&self, vert: &Vertex, worldspace: Vector3<f32>, screenspace: Vector3<f32>) -> Vector4<f32> {
-    let bn = (vert.varying_normal * worldspace).normalize();
-    let mut uv = vert.varying_uv * screenspace;
-    uv.y = 1.0 - uv.y;
+  // `ao` is the screen-space ambient occlusion factor, which darkens all
+  //   but emitted light.
+  //   `duv` holds the screen-space UV derivatives used for texture filtering.
+  pub fn fragment(&self, vert: &Vertex, bar: Vector3<f32>, ao: f32, duv: (Vector2<f32>, Vector2<f32>)) -> Vector4<f32> {
+    let pos = vert.view_triangle * bar;
+    // Flat shading lights the whole face at its centroid, though textures
+    //   are still looked up per pixel.
+    let (bn, lit) = match self.shading {
+      Shading::Flat => (face_normal(vert), vert.view_triangle * Vector3::repeat(1.0 / 3.0)),
+      _ => ((vert.varying_normal * bar).normalize(), pos),
+    };
+    let uv = match self.height_map.loaded && self.shading == Shading::Phong {
+      true => self.parallax(vert, bn, &pos, vert.varying_uv * bar, duv),
+      false => vert.varying_uv * bar,
+    };
+    let sample = |map: &Texture| map.sample(uv, duv.0, duv.1);
+
+    let normal = match self.normal_map.loaded && self.shading == Shading::Phong {
+      true => {
+        let texel = (sample(&self.normal_map).xyz() * 2.0).add_scalar(-1.0);
+        match self.normal_space {
+          NormalSpace::Tangent => match self.tangent_basis(vert, bn) {
+            Some(b) => (b * texel).normalize(),
+            None => bn,
+          },
+          NormalSpace::Object => (self.model_view_it * texel.push(0.0)).xyz().normalize(),
+        }
+      },
+      false => bn,
+    };
+
+    match self.mode {
+      Mode::Normals => return debug_color((normal * 0.5).add_scalar(0.5)),
+      Mode::WorldNormals => return debug_color(((self.model_view.transpose() * normal.push(0.0)).xyz() * 0.5).add_scalar(0.5)),
+      Mode::Depth => {
+        // Linear view depth across the model's bounding sphere, near = white.
+        let center = (self.model_view * self.model.center.push(1.0)).z;
+        let r = self.model.radius();
+        return debug_color(ONES.xyz() * ((center + r - pos.z) / (2.0 * r)).clamp(0.0, 1.0));
+      },
+      Mode::Uv => {
+        let checker = ((uv * 8.0).map(|x| x.floor() as i32).sum() & 1) as f32;
+        return debug_color(Vector3::new(uv.x, uv.y, 0.5) * (0.5 + 0.5 * checker));
+      },
+      Mode::Matcap => {
+        // View-space +x points to the left of the screen.
+        let uv = Vector2::new(0.5 - normal.x * 0.5, 0.5 + normal.y * 0.5);
+        let color = match self.matcap_map.loaded {
+          true => self.matcap_map.sample_lod(uv, 0.0).xyz(),
+          // Plain clay, lit from the viewer.
+          false => Vector3::repeat(0.8 * (-normal.z).max(0.0)),
+        };
+        return (color * ao).push(1.0);
+      },
+      // Mirrored UVs flip one of the two but not the other, so comparing
+      //   the views shows where the basis changes handedness.
+      Mode::Tangents | Mode::Bitangents => return match self.tangent_basis(vert, bn) {
+        Some(b) => debug_color((b.column((self.mode == Mode::Bitangents) as usize) * 0.5).add_scalar(0.5)),
+        None => debug_color(Vector3::zeros()),
+      },
+      _ => (),
+    }
+
+    let (color, emissive) = self.shade(&normal, &lit, uv, duv, &self.albedo(uv, duv));
+    (color * ao + emissive).push(1.0)
+  }
+
+  // The diffuse map, if any, tinted by the material's diffuse color.
+  fn albedo(&self, uv: Vector2<f32>, duv: (Vector2<f32>, Vector2<f32>)) -> Vector3<f32> {
+    (match self.diffuse_map.loaded {
+      true => self.diffuse_map.sample(uv, duv.0, duv.1).xyz(),
+      false => ONES.xyz(),
+    }).component_mul(&self.material.diffuse)
+  }
+
+  // Light reflected towards the viewer at `pos`, given the surface's normal,
+  //   texture coordinates and albedo, and light emitted there.
+  fn shade(&self, normal: &Vector3<f32>, pos: &Vector3<f32>, uv: Vector2<f32>, duv: (Vector2<f32>, Vector2<f32>), albedo: &Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+    let sample = |map: &Texture| map.sample(uv, duv.0, duv.1);
+
+    // Blinn-Phong.  A specular map's texel s (in [0, 1]) scales both the
+    //   highlight's intensity and its exponent, which becomes 1 + s * shininess.
+    let (spec_intensity, spec_exponent) = match self.specular_map.loaded {
+      true => {
+        let s = sample(&self.specular_map).z;
+        (s, 1.0 + s * self.material.shininess)
+      },
+      false => (1.0, self.material.shininess),
+    };
+
+    // Cook-Torrance inputs.  Each map, when loaded, replaces its constant;
+    //   scalar maps are read from the blue channel like the specular map.
+    let scalar = |map: &Texture, constant: f32| match map.loaded {
+      true => sample(map).z,
+      false => constant,
+    };
+    let metallic = scalar(&self.metallic_map, self.material.metallic);
+    let roughness = scalar(&self.roughness_map, self.material.roughness);
+
+    let view = -pos.normalize();
+
+    // An environment map replaces the constant ambient term.  Lookups are
+    //   in world space.
+    let ambient = match &self.environment {
+      Some(env) => {
+        let world = |v: Vector3<f32>| (self.model_view_inv * v.push(0.0)).xyz();
+        let irradiance = env.irradiance(&world(*normal));
+        let reflected = world(*normal * (2.0 * normal.dot(&view)) - view);
+        match (self.mode, self.material.brdf) {
+          (Mode::Toon, _) => irradiance.component_mul(albedo),
+          (_, Brdf::BlinnPhong) => {
+            // Roughness with a similar highlight width to the exponent.
+            let rough = (2.0 / (spec_exponent + 2.0)).sqrt();
+            irradiance.component_mul(albedo) + self.material.specular.component_mul(&env.radiance(&reflected, rough)) * spec_intensity
+          },
+          (_, Brdf::CookTorrance) => {
+            let radiance = env.radiance(&reflected, roughness);
+            material::cook_torrance_ibl(normal, &view, albedo, metallic, roughness, &irradiance, &radiance)
+          },
+        }
+      },
+      None => self.material.ambient.component_mul(albedo),
+    };
+
+    let (mut color, emissive) = match self.material.brdf {
+      Brdf::BlinnPhong => (ambient, Vector3::zeros()),
+      Brdf::CookTorrance => {
+        let occlusion = scalar(&self.ao_map, self.material.ao);
+        let emissive = match self.emissive_map.loaded {
+          true => sample(&self.emissive_map).xyz(),
+          false => self.material.emissive,
+        };
+        (ambient * occlusion, emissive)
+      },
+    };
+    for (light, shadow) in self.uniform_lights.iter().zip(&self.shadows) {
+      let (l, radiance) = light.incident(pos);
+      let n_dot_l = normal.dot(&l);
+      if n_dot_l <= 0.0 {
+        continue;
+      }
+
+      let visibility = match shadow {
+        Some(shadow) => shadow.visibility(&(self.model_view_inv * pos.push(1.0)).xyz(), n_dot_l),
+        None => 1.0,
+      };
+
+      color += match (self.mode, self.material.brdf) {
+        (Mode::Toon, _) => {
+          let bands = self.toon_bands as f32;
+          let diffuse = (n_dot_l * visibility * bands).ceil() / bands;
+          let highlight = normal.dot(&(l + view).normalize()).max(0.0).powf(spec_exponent) * spec_intensity * visibility;
+          albedo * diffuse + self.material.specular * (highlight > 0.5) as i32 as f32
+        },
+        (_, Brdf::BlinnPhong) => {
+          let specular = normal.dot(&(l + view).normalize()).max(0.0).powf(spec_exponent) * spec_intensity;
+          (albedo * n_dot_l + self.material.specular * specular) * visibility
+        },
+        (_, Brdf::CookTorrance) => material::cook_torrance(normal, &view, &l, albedo, metallic, roughness) * visibility,
+      }.component_mul(&radiance);
+    }
+
+    (color, emissive)
+  }
+
+  // Gouraud lighting at a face's corner, split around the albedo so that
+  //   the diffuse map still modulates each pixel, as fixed-function texturing
+  //   does: albedo * diffuse + specular, plus emission.  Reflection is
+  //   affine in the albedo except for partly metallic PBR materials, where
+  //   this is an approximation.
+  fn corner(&self, vert: &Vertex, j: usize) -> [Vector3<f32>; 3] {
+    let normal = vert.varying_normal.column(j).normalize();
+    let pos = vert.view_triangle.column(j).into_owned();
+    let uv = vert.varying_uv.column(j).into_owned();
+    let duv = (Vector2::zeros(), Vector2::zeros());
+    let (black, emissive) = self.shade(&normal, &pos, uv, duv, &Vector3::zeros());
+    let (white, _) = self.shade(&normal, &pos, uv, duv, &ONES.xyz());
+    [white - black, black, emissive]
+  }
+
+  // Opacity times the diffuse map's alpha, sampled at full resolution.
+  fn alpha(&self, vert: &Vertex, bar: Vector3<f32>) -> f32 {
+    match self.diffuse_map.loaded {
+      true => self.material.opacity * self.diffuse_map.sample(vert.varying_uv * bar, Vector2::zeros(), Vector2::zeros()).w,
+      false => self.material.opacity,
+    }
+  }
+
+  // Parallax occlusion mapping: marches the view ray down through the height
+  //   map (1 = surface, 0 = deepest) in equal steps and returns the UV
+  //   where it first passes below, interpolated between the last two steps.
+  fn parallax(&self, vert: &Vertex, bn: Vector3<f32>, pos: &Vector3<f32>, uv: Vector2<f32>, duv: (Vector2<f32>, Vector2<f32>)) -> Vector2<f32> {
+    // The basis is only orthonormal for undistorted UVs, so the view
+    //   direction is brought into UV space with its inverse.
+    let inverse = match self.tangent_basis(vert, bn).and_then(|b| b.try_inverse()) {
+      Some(b) => b,
+      None => return uv,
+    };
+    let view = inverse * -pos.normalize();
+    if view.z <= 0.0 {
+      return uv;
+    }
+
+    let steps = self.parallax_steps.max(1);
+    let step = 1.0 / steps as f32;
+    // Limits the offset at grazing angles.
+    let delta = view.xy() / view.z.max(0.1) * (self.parallax_scale * step);
+    let depth_at = |uv: Vector2<f32>| 1.0 - self.height_map.sample(uv, duv.0, duv.1).z;
 
-    let ai = match Matrix3::from_columns(&[
+    let (mut uv, mut depth, mut surface) = (uv, 0.0, depth_at(uv));
+    for _ in 0..steps {
+      if depth >= surface {
+        break;
+      }
+      uv -= delta;
+      depth += step;
+      surface = depth_at(uv);
+    }
+
+    let after = surface - depth;
+    let before = depth_at(uv + delta) - (depth - step);
+    match after - before {
+      d if d < 0.0 => {
+        let weight = after / d;
+        (uv + delta) * weight + uv * (1.0 - weight)
+      },
+      _ => uv,
+    }
+  }
+
+  // Darboux frame (tangent, bitangent, normal) for tangent-space normal maps.
+  fn tangent_basis(&self, vert: &Vertex, bn: Vector3<f32>) -> Option<Matrix3<f32>> {
+    let ai = Matrix3::from_columns(&[
       vert.view_triangle.column(1) - vert.view_triangle.column(0),
       vert.view_triangle.column(2) - vert.view_triangle.column(0),
       bn
-    ]).try_inverse() {
-      Some(ai) => ai,
-      None => { return Vector4::zeros(); }
-    };
+    ]).transpose().try_inverse()?;
     let i = ai * Vector3::new(vert.varying_uv.m12 - vert.varying_uv.m11, vert.varying_uv.m13 - vert.varying_uv.m11, 0.0);
     let j = ai * Vector3::new(vert.varying_uv.m22 - vert.varying_uv.m21, vert.varying_uv.m23 - vert.varying_uv.m21, 0.0);
-    let b = Matrix3::from_columns(&[
+
+    Some(Matrix3::from_columns(&[
       i.normalize(),
       j.normalize(),
       bn
-    ]).transpose();
-
-    let normal = (b * (match self.normal_map.loaded {
-      true => self.normal_map.get(uv).zyx(),
-      false => worldspace,
-    })).normalize();
-    let specular = match self.specular_map.loaded {
-      true => self.specular_map.get(uv).z,
-      false => 0.0,
-    };
+    ]))
+  }
 
-    let factor = match self.diffuse_map.loaded {
-        true => 2.0,
-        false => 1.0,
-    };
-    let result_diffuse = normal.dot(&self.uniform_light).max((factor - 1.0) * 0.65);
-    let diffuse_contrib = ((normal * result_diffuse * 2.0) - self.uniform_light).normalize();
-    let result_specular = (-diffuse_contrib.z).max(0.0).powf(5.0 + specular);
+  pub fn add_light(&mut self, light: Light) {
+    self.lights.push(light);
+    self.update_lights();
+  }
 
-    let diffuse = (match self.diffuse_map.loaded {
-      true => self.diffuse_map.get(uv),
-      false => ONES * 255.0,
-    }).scale(factor * (result_diffuse + result_specular)).add_scalar(10.0);
-    Vector4::new(
-      diffuse.x.clamp(0.0, 255.0),
-      diffuse.y.clamp(0.0, 255.0),
-      diffuse.z.clamp(0.0, 255.0),
-      1.0
-    )
+  // Lights move with the camera, so orbiting keeps the model lit from the
+  //   same side.  Their coordinates are relative to the model's center as
+  //   seen on screen (x right, y up, z towards the viewer), which are the
+  //   world axes in the starting view.  Shadow maps are rebuilt to follow.
+  fn update_lights(&mut self) {
+    let center = (self.model_view * self.model.center.push(1.0)).xyz();
+    // View-space x points left, and z away from the viewer.
+    let frame = Matrix4::new(
+      -1.0, 0.0, 0.0, center.x,
+      0.0, 1.0, 0.0, center.y,
+      0.0, 0.0, -1.0, center.z,
+      0.0, 0.0, 0.0, 1.0,
+    );
+    self.uniform_lights = self.lights.iter().map(|l| l.transform(&frame)).collect();
+
+    let world = self.model_view_inv * frame;
+    self.shadows = self.lights.iter().map(|l| self.shadow_map(&l.transform(&world))).collect();
   }
 
-  pub fn set_light(&mut self, x: f32, y: f32, z: f32) {
-    self.light = Vector4::new(x, y, z, 0.0).normalize();
-    self.uniform_light = (self.model_view * self.light).xyz();
+  // Shadow map resolution for directional lights; 0 disables shadows.
+  pub fn set_shadow_size(&mut self, size: usize) {
+    self.shadow_size = size;
+    self.update_lights();
+  }
+
+  fn shadow_map(&self, light: &Light) -> Option<ShadowMap> {
+    match (light.kind, self.shadow_size) {
+      (_, 0) => None,
+      (light::Kind::Directional, size) => Some(ShadowMap::new(self.model, &light.direction, size)),
+      _ => None,
+    }
+  }
+
+  pub fn set_material(&mut self, material: Material) {
+    self.material = material;
   }
 
   pub fn set_projection(&mut self, f: f32) {
@@ -154,6 +546,9 @@ impl Shader<'_> {
     );
 
     self.model_view = m_inv * tr;
+    self.model_view_inv = self.model_view.try_inverse().unwrap();
+    self.model_view_it = self.model_view_inv.transpose();
+    self.update_lights();
   }
 
   pub fn set_viewport(&mut self, x: f32, y: f32, w: f32, h: f32) {
@@ -165,56 +560,438 @@ impl Shader<'_> {
     );
   }
 
+  // Draws a frame over the previous one.
   pub fn render(&mut self) {
-    let result: Vec<Vec<(i32, f32, String)>> =
+    let frame = self.frame();
+    let _ = io::stdout().write_all(format!("\x1b[0m\x1b[0H{}", frame).as_bytes());
+  }
+
+  // Prints a frame as ordinary output, e.g. into a log or a file.
+  pub fn print(&mut self) {
+    let frame = self.frame();
+    let _ = io::stdout().write_all(frame.as_bytes());
+  }
+
+  // Rows of terminal cells, each line ending with its styles reset so that
+  //   no color spills past it.
+  fn frame(&mut self) -> String {
+    // Supersampling draws at n times the resolution, with each pixel's
+    //   samples spread evenly around its center.
+    let n = self.supersampling as i32;
+    let (width, height, viewport) = (self.width, self.height, self.viewport);
+    (self.width, self.height) = (width * n, height * n);
+    self.viewport = Matrix4::new_translation(&Vector3::new((n - 1) as f32 / 2.0, (n - 1) as f32 / 2.0, 0.0))
+      * Matrix4::new_nonuniform_scaling(&Vector3::new(n as f32, n as f32, 1.0))
+      * viewport;
+    let fb = self.draw();
+    (self.width, self.height, self.viewport) = (width, height, viewport);
+
+    // Debug views are displayed as they are.  Samples are tone mapped
+    //   before they are combined, so highlights don't swamp their neighbors.
+    let (scale, tonemap) = match self.mode {
+      Mode::Shaded | Mode::Toon => (self.exposure.exp2(), self.tonemap),
+      _ => (1.0, Tonemap::Clamp),
+    };
+    let samples: Vec<Vector4<f32>> = fb.color.par_iter().map(|v| tonemap.apply(v.xyz() * scale).push(v.w)).collect();
+    let colors = post::downsample(&samples, width, height, n, self.downsample);
+
+    let mut pixels: Vec<Option<Vector3<u8>>> = colors.iter().map(|v| match v.w {
+      0.0 => None,
+      _ => Some(color::encode(v.xyz() * v.w)),
+    }).collect();
+    // ASCII output only reads brightness, which dithering would only add
+    //   noise to.
+    if !matches!(self.glyphs, Glyphs::Ascii { .. }) {
+      self.palette.quantize(&mut pixels, self.width as usize, self.dither);
+    }
+
+    let (cw, ch) = self.cell_size();
+    let mut esc = String::new();
+    let mut prev = String::new();
+    let mut block = Vec::with_capacity((cw * ch) as usize);
+    for y in 1..self.height / ch {
+      for x in 1..self.width / cw {
+        block.clear();
+        for py in y * ch..(y + 1) * ch {
+          block.extend((x * cw..(x + 1) * cw).map(|px| pixels[(px + py * self.width) as usize]));
+        }
+
+        let (style, glyph) = self.glyphs.encode(&block, self.palette);
+        if style != prev {
+          esc += &style;
+          prev = style;
+        }
+        esc.push(glyph);
+      }
+      if !prev.is_empty() {
+        esc += "\x1b[0m";
+        prev.clear();
+      }
+      esc += "\n";
+    }
+    esc
+  }
+
+  // Rasterizes and shades the model at the shader's resolution.
+  fn draw(&self) -> Framebuffer {
+    let verts: Vec<Vertex> =
       (0..self.model.nfaces()).into_par_iter().map(|i| {
         let mut vert = Vertex::new();
         for j in 0..3 {
           vert.clip[j] = self.vertex(&mut vert, i, j);
         }
-        vert.triangle(self)
+        vert
       }).collect();
 
-    let mut pixels: HashMap<i32, (f32, String)> = HashMap::with_capacity((self.width * self.height) as usize);
-    for items in result {
-      for (idx, z, color) in items {
-        if let Some((prev_z, _)) = pixels.get(&idx) {
-          if *prev_z < z {
-            continue;
-          }
+    // Overdraw counts every fragment anyway, so blending is skipped there.
+    let alpha = match (self.mode, self.material.alpha) {
+      (Mode::Overdraw, Alpha::Blend) => Alpha::Opaque,
+      (_, alpha) => alpha,
+    };
+
+    let mut fb = Framebuffer::new(self.width, self.height);
+    let result: Vec<Vec<(usize, Fragment, f32)>> =
+      verts.par_iter().enumerate().map(|(i, vert)| {
+        vert.triangle(self, &fb, i).into_iter().map(|(idx, frag)| match alpha {
+          Alpha::Opaque => (idx, frag, 1.0),
+          _ => (idx, frag, self.alpha(vert, frag.bar)),
+        }).collect()
+      }).collect();
+    for (idx, frag, a) in result.into_iter().flatten() {
+      match alpha {
+        Alpha::Mask if a < self.material.alpha_cutoff => (),
+        Alpha::Blend if a <= 0.0 => (),
+        Alpha::Blend if a < 1.0 => fb.add_translucent(idx, frag, a),
+        _ => fb.depth_test(idx, frag),
+      }
+    }
+
+    fb.resolve_geometry(&verts);
+    if self.ssao_radius > 0.0 {
+      fb.ao = ssao::occlusion(&fb, &(self.viewport * self.projection), self.ssao_radius * self.model.radius());
+    }
+
+    // Gouraud shading lights each visible face's corners once.  Other views
+    //   are still drawn per pixel.
+    let corners: Vec<Option<[[Vector3<f32>; 3]; 3]>> = match (self.shading, self.mode) {
+      (Shading::Gouraud, Mode::Shaded | Mode::Toon) => {
+        let mut visible = vec![false; verts.len()];
+        for f in fb.fragments.iter().flatten() {
+          visible[f.face] = true;
         }
+        verts.par_iter().zip(visible).map(|(vert, visible)| {
+          visible.then(|| [0, 1, 2].map(|j| self.corner(vert, j)))
+        }).collect()
+      },
+      _ => vec![],
+    };
+
+    fb.color = (0..fb.fragments.len()).into_par_iter().map(|idx| match (fb.fragments[idx], self.mode) {
+      (Some(_), Mode::Overdraw) => debug_color(heat(fb.overdraw[idx])),
+      (Some(f), _) => match corners.get(f.face) {
+        Some(Some(c)) => {
+          let [diffuse, specular, emissive] = [0, 1, 2].map(|k| c[0][k] * f.bar.x + c[1][k] * f.bar.y + c[2][k] * f.bar.z);
+          let albedo = self.albedo(verts[f.face].varying_uv * f.bar, fb.uv_derivatives(idx));
+          ((albedo.component_mul(&diffuse) + specular) * fb.ao[idx] + emissive).push(1.0)
+        },
+        _ => self.fragment(&verts[f.face], f.bar, fb.ao[idx], fb.uv_derivatives(idx)),
+      },
+      (None, _) => Vector4::zeros(),
+    }).collect();
+
+    if let (Background::Environment | Background::Color(_), Mode::Shaded | Mode::Toon) = (self.background, self.mode) {
+      self.draw_background(&mut fb);
+    }
+
+    if let Mode::Toon = self.mode {
+      post::outline(&mut fb, self.outline_color.push(1.0), OUTLINE_DEPTH * self.model.radius(), OUTLINE_CREASE, self.supersampling as i32);
+    }
+
+    if self.fog != Fog::Off {
+      let (fragments, position) = (&fb.fragments, &fb.position);
+      fb.color.par_iter_mut().enumerate().filter(|(idx, _)| fragments[*idx].is_some()).for_each(|(idx, c)| {
+        *c = self.fog(c.xyz(), &position[idx]).push(c.w);
+      });
+    }
+
+    if !fb.translucent.is_empty() {
+      self.blend(&mut fb, &verts);
+    }
+
+    if self.wireframe != Wireframe::Off {
+      self.draw_wireframe(&mut fb, &verts);
+    }
+
+    fb
+  }
+
+  pub fn set_glyphs(&mut self, glyphs: Glyphs) {
+    self.glyphs = glyphs;
+  }
+
+  pub fn set_palette(&mut self, palette: Palette, dither: Dither) {
+    self.palette = palette;
+    self.dither = dither;
+  }
+
+  // Pixels per terminal cell, horizontally and vertically.
+  pub fn cell_size(&self) -> (i32, i32) {
+    let (w, h) = self.glyphs.pixels();
+    (w as i32, h as i32)
+  }
+
+  pub fn set_mode(&mut self, mode: Mode) {
+    self.mode = mode;
+  }
+
+  pub fn set_toon_bands(&mut self, bands: u32) {
+    self.toon_bands = bands.max(1);
+  }
+
+  pub fn set_tonemap(&mut self, tonemap: Tonemap) {
+    self.tonemap = tonemap;
+  }
+
+  pub fn set_exposure(&mut self, stops: f32) {
+    self.exposure = stops;
+  }
+
+  pub fn adjust_exposure(&mut self, stops: f32) {
+    self.exposure += stops;
+  }
+
+  pub fn set_environment(&mut self, environment: Environment) {
+    self.environment = Some(environment);
+  }
+
+  pub fn set_background(&mut self, background: Background) {
+    self.background = background;
+  }
+
+  pub fn set_fog(&mut self, fog: Fog) {
+    self.fog = fog;
+  }
+
+  pub fn set_fog_range(&mut self, start: f32, end: f32) {
+    self.fog_range = (start, end);
+  }
+
+  pub fn set_fog_density(&mut self, density: f32) {
+    self.fog_density = density;
+  }
+
+  pub fn set_parallax(&mut self, scale: f32, steps: u32) {
+    self.parallax_scale = scale;
+    self.parallax_steps = steps;
+  }
+
+  // Renders n×n samples per terminal cell, combined with `filter`.
+  pub fn set_supersampling(&mut self, n: u32, filter: Downsample) {
+    self.supersampling = n.max(1);
+    self.downsample = filter;
+  }
+
+  pub fn set_outline_color(&mut self, color: Vector3<f32>) {
+    self.outline_color = color;
+  }
+
+  // Background color seen along the view-space direction `dir`, if it isn't
+  //   left to the terminal.
+  fn background_color(&self, dir: &Vector3<f32>) -> Option<Vector3<f32>> {
+    match (self.background, &self.environment) {
+      (Background::Color(c), _) => Some(c),
+      (Background::Environment, Some(env)) => Some(env.radiance(&(self.model_view_inv * dir.push(0.0)).xyz(), 0.0)),
+      _ => None,
+    }
+  }
+
+  // Blends `color` at view-space position `pos` toward the background, or
+  //   black if there is none.  Debug views are left alone.
+  fn fog(&self, color: Vector3<f32>, pos: &Vector3<f32>) -> Vector3<f32> {
+    let radius = self.model.radius();
+    let front = (self.model_view * self.model.center.push(1.0)).z - radius;
+    let depth = ((pos.z - front) / (2.0 * radius)).max(0.0);
+
+    let (start, end) = self.fog_range;
+    let amount = match (self.mode, self.fog) {
+      (Mode::Shaded | Mode::Toon, Fog::Linear) => ((depth - start) / (end - start).max(f32::EPSILON)).clamp(0.0, 1.0),
+      (Mode::Shaded | Mode::Toon, Fog::Exponential) => 1.0 - (-self.fog_density * depth).exp(),
+      (Mode::Shaded | Mode::Toon, Fog::ExponentialSquared) => 1.0 - (-(self.fog_density * depth).powi(2)).exp(),
+      _ => return color,
+    };
+    color.lerp(&self.background_color(pos).unwrap_or(Vector3::zeros()), amount)
+  }
 
-        pixels.insert(idx, (z, color));
+  // Fills uncovered pixels with the background seen along each pixel's ray.
+  fn draw_background(&self, fb: &mut Framebuffer) {
+    let viewport_inv = self.viewport.try_inverse().unwrap();
+    // Inverts the perspective divide by -z / f done by the projection.
+    let k = -self.projection.m43;
+
+    let width = self.width;
+    let height = self.height;
+    fb.color.par_iter_mut().enumerate().filter(|(_, c)| c.w == 0.0).for_each(|(idx, c)| {
+      let (x, y) = (idx as i32 % width, height - idx as i32 / width);
+      let ndc = viewport_inv * Vector4::new(x as f32, y as f32, 0.0, 1.0);
+      if let Some(color) = self.background_color(&Vector3::new(-k * ndc.x, k * ndc.y, 1.0)) {
+        *c = color.push(1.0);
       }
+    });
+  }
+
+  // Shades the translucent fragments in front of the opaque surface and
+  //   composites them back to front.  No neighbors are available for UV
+  //   derivatives, so their textures are sampled at full resolution.
+  fn blend(&self, fb: &mut Framebuffer, verts: &[Vertex]) {
+    let mut layers = std::mem::take(&mut fb.translucent);
+    layers.retain(|(idx, frag, _)| fb.depth(*idx).is_none_or(|d| frag.depth < d));
+    layers.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.depth.total_cmp(&a.1.depth)));
+
+    let colors: Vec<Vector4<f32>> = layers.par_iter().map(|(_, frag, _)| {
+      let vert = &verts[frag.face];
+      let color = self.fragment(vert, frag.bar, 1.0, (Vector2::zeros(), Vector2::zeros()));
+      self.fog(color.xyz(), &(vert.view_triangle * frag.bar)).push(color.w)
+    }).collect();
+    for ((idx, _, a), color) in layers.iter().zip(colors) {
+      let dst = fb.color[*idx];
+      fb.color[*idx] = (color.xyz() * *a + dst.xyz() * dst.w * (1.0 - a)).push(1.0);
     }
+  }
 
-    let mut esc = String::from("\x1b[0m\x1b[0H");
-    let mut prev = String::new();
-    let default = (0.0, String::from("\x1b[0m "));
-    for y in 1..self.height {
-      for x in 1..self.width {
-        let (_, cell) = pixels.get(&(x + (y * self.width))).unwrap_or(&default);
-
-        if *cell == prev {
-          esc += " ";
-        } else {
-          esc += cell;
-          prev.clone_from(cell);
+  // Replaces the shaded image with triangle edges.
+  fn draw_wireframe(&self, fb: &mut Framebuffer, verts: &[Vertex]) {
+    let lines: Vec<Vec<(usize, f32)>> = verts.par_iter().map(|vert| {
+      let mut out = vec![];
+      // Lines are n samples thick, one pixel once downsampled.
+      let n = self.supersampling as i32;
+      vert.edges(&self.viewport, |x, y, depth| {
+        for (dx, dy) in (0..n * n).map(|i| (i % n - n / 2, i / n - n / 2)) {
+          if let Some(idx) = fb.index(x + dx, y + dy) {
+            out.push((idx, depth));
+          }
         }
+      });
+      out
+    }).collect();
+
+    let bias = WIREFRAME_BIAS * self.model.radius();
+    let color = self.material.diffuse.push(1.0);
+    let mut wires = vec![Vector4::zeros(); fb.color.len()];
+    for (idx, depth) in lines.into_iter().flatten() {
+      let hidden = match (self.wireframe, fb.depth(idx)) {
+        (Wireframe::HiddenLine, Some(d)) => depth > d + bias,
+        _ => false,
+      };
+      if !hidden {
+        wires[idx] = color;
       }
-      esc += "\n";
     }
-    let _ = io::stdout().write_all(esc.as_bytes());
+    fb.color = wires;
+  }
+
+  pub fn set_shading(&mut self, shading: Shading) {
+    self.shading = shading;
   }
 
-  pub fn set_diffuse(&mut self, filename: &String) {
-    self.diffuse_map.load(filename);
+  // Cycles through flat, Gouraud and Phong.
+  pub fn toggle_shading(&mut self) {
+    self.shading = match self.shading {
+      Shading::Flat => Shading::Gouraud,
+      Shading::Gouraud => Shading::Phong,
+      Shading::Phong => Shading::Flat,
+    };
   }
-  pub fn set_normal(&mut self, filename: &String) {
-    self.normal_map.load(filename);
+
+  pub fn set_wireframe(&mut self, wireframe: Wireframe) {
+    self.wireframe = wireframe;
   }
-  pub fn set_specular(&mut self, filename: &String) {
-    self.specular_map.load(filename);
+
+  // Cycles through off, all edges and hidden-line.
+  pub fn toggle_wireframe(&mut self) {
+    self.wireframe = match self.wireframe {
+      Wireframe::Off => Wireframe::All,
+      Wireframe::All => Wireframe::HiddenLine,
+      Wireframe::HiddenLine => Wireframe::Off,
+    };
+  }
+
+  // Ambient occlusion sampling radius as a fraction of the model's size;
+  //   0 disables the pass.
+  pub fn set_ssao(&mut self, radius: f32) {
+    self.ssao_radius = radius;
+  }
+
+  // Looks up a texture slot by name (as used on the command line).
+  fn map_mut(&mut self, name: &str) -> Option<&mut Texture> {
+    match name {
+      "diffuse" => Some(&mut self.diffuse_map),
+      "normal" => Some(&mut self.normal_map),
+      "specular" => Some(&mut self.specular_map),
+      "metallic" => Some(&mut self.metallic_map),
+      "roughness" => Some(&mut self.roughness_map),
+      "ao" => Some(&mut self.ao_map),
+      "emissive" => Some(&mut self.emissive_map),
+      "height" => Some(&mut self.height_map),
+      "matcap" => Some(&mut self.matcap_map),
+      _ => None,
+    }
+  }
+
+  // Applies `f` to the named texture, or to every texture if `map` is None.
+  fn configure_maps<F>(&mut self, map: Option<&str>, f: F) -> Result<(), String> where F: Fn(&mut Texture) {
+    let names = match map {
+      Some(name) => vec![name],
+      None => vec!["diffuse", "normal", "specular", "metallic", "roughness", "ao", "emissive", "height", "matcap"],
+    };
+    for name in names {
+      f(self.map_mut(name).ok_or(format!("Unrecognized texture: {}", name))?);
+    }
+    Ok(())
+  }
+
+  pub fn set_filter(&mut self, map: Option<&str>, filter: Filter) -> Result<(), String> {
+    self.configure_maps(map, |t| t.filter = filter)
+  }
+
+  pub fn set_wrap(&mut self, map: Option<&str>, wrap: Wrap) -> Result<(), String> {
+    self.configure_maps(map, |t| t.wrap = [wrap; 2])
+  }
+
+  pub fn load_map(&mut self, name: &str, filename: &String) {
+    match self.map_mut(name) {
+      Some(map) => map.load(filename),
+      None => panic!("Unrecognized texture: {}", name),
+    }
+  }
+
+  pub fn set_normal_space(&mut self, space: NormalSpace) {
+    self.normal_space = space;
+  }
+}
+
+// View-space geometric normal, facing the camera.
+fn face_normal(vert: &Vertex) -> Vector3<f32> {
+  let n = (vert.view_triangle.column(1) - vert.view_triangle.column(0))
+    .cross(&(vert.view_triangle.column(2) - vert.view_triangle.column(0)))
+    .normalize();
+  match n.dot(&vert.view_triangle.column(0)) > 0.0 {
+    true => -n,
+    false => n,
+  }
+}
+
+// Opaque fragment that displays as `c`, bypassing the linear pipeline.
+fn debug_color(c: Vector3<f32>) -> Vector4<f32> {
+  color::decode(c).push(1.0)
+}
+
+// Blue (drawn once) through green and yellow to red (drawn 4+ times).
+fn heat(count: u32) -> Vector3<f32> {
+  match count {
+    0 | 1 => Vector3::new(0.0, 0.0, 1.0),
+    2 => Vector3::new(0.0, 1.0, 0.0),
+    3 => Vector3::new(1.0, 1.0, 0.0),
+    _ => Vector3::new(1.0, 0.0, 0.0),
   }
 }
 
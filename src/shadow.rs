@@ -0,0 +1,106 @@
+/**
+ * shadow.rs: Shadow maps for directional lights.
+ */
+
+use nalgebra::{Vector3, Matrix4};
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+
+use crate::model::Model;
+use crate::vertex::Vertex;
+
+// Depth bias, in shadow map texels, at normal and grazing incidence.
+const BIAS: f32 = 1.5;
+const SLOPE_BIAS: f32 = 4.0;
+
+// Half-width of the percentage-closer filtering kernel.
+const PCF_RADIUS: i32 = 1;
+
+#[derive(Debug)]
+pub struct ShadowMap {
+  size: i32,
+  depth: Vec<f32>,
+
+  // World space to shadow map pixel coordinates (x, y) and light depth (z).
+  matrix: Matrix4<f32>,
+  texel: f32,
+}
+
+impl ShadowMap {
+  // Renders the model's depth as seen along `direction` with an orthographic
+  //   projection fitted to its bounding sphere.
+  pub fn new(model: &Model, direction: &Vector3<f32>, size: usize) -> ShadowMap {
+    let size = size as i32;
+    let r = model.radius();
+    let c = model.center;
+
+    let z = direction.normalize();
+    let up = match z.y.abs() > 0.99 {
+      true => Vector3::x(),
+      false => Vector3::y(),
+    };
+    let x = up.cross(&z).normalize();
+    let y = z.cross(&x);
+
+    let projection = Matrix4::new(
+      x.x / r, x.y / r, x.z / r, -x.dot(&c) / r,
+      y.x / r, y.y / r, y.z / r, -y.dot(&c) / r,
+      z.x, z.y, z.z, -z.dot(&c),
+      0.0, 0.0, 0.0, 1.0,
+    );
+    let half = size as f32 / 2.0;
+    let viewport = Matrix4::new(
+      half, 0.0, 0.0, half,
+      0.0, half, 0.0, half,
+      0.0, 0.0, 1.0, 0.0,
+      0.0, 0.0, 0.0, 1.0,
+    );
+
+    let result: Vec<Vec<(usize, f32)>> =
+      (0..model.nfaces()).into_par_iter().map(|i| {
+        let mut vert = Vertex::new();
+        for j in 0..3 {
+          vert.clip[j] = projection * model.vert(i, j);
+        }
+
+        let mut out = vec![];
+        vert.rasterize(&viewport, size, size, |x, y, _, depth| {
+          if x < size && y < size {
+            out.push(((x + y * size) as usize, depth));
+          }
+        });
+        out
+      }).collect();
+
+    let mut depth = vec![f32::MAX; (size * size) as usize];
+    for (idx, z) in result.into_iter().flatten() {
+      depth[idx] = depth[idx].min(z);
+    }
+
+    ShadowMap {
+      size,
+      depth,
+      matrix: viewport * projection,
+      texel: (2.0 * r) / size as f32,
+    }
+  }
+
+  // Fraction of the PCF kernel around `pos` (world space) that is lit.
+  pub fn visibility(&self, pos: &Vector3<f32>, n_dot_l: f32) -> f32 {
+    let p = self.matrix * pos.push(1.0);
+    let bias = self.texel * (BIAS + SLOPE_BIAS * (1.0 - n_dot_l.clamp(0.0, 1.0)));
+    let (px, py) = (p.x as i32, p.y as i32);
+
+    let mut lit = 0;
+    let mut total = 0;
+    for dy in -PCF_RADIUS..=PCF_RADIUS {
+      for dx in -PCF_RADIUS..=PCF_RADIUS {
+        let (x, y) = (px + dx, py + dy);
+        total += 1;
+        if x < 0 || y < 0 || x >= self.size || y >= self.size || p.z - bias <= self.depth[(x + y * self.size) as usize] {
+          lit += 1;
+        }
+      }
+    }
+    lit as f32 / total as f32
+  }
+}
@@ -0,0 +1,68 @@
+/**
+ * ssao.rs: Screen-space ambient occlusion.
+ */
+
+use nalgebra::{Vector3, Matrix4};
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+
+use crate::framebuffer::Framebuffer;
+
+const SAMPLES: usize = 24;
+const BIAS: f32 = 0.02;
+
+// Estimates, for each covered pixel, the fraction of the normal-oriented
+//   hemisphere of `radius` (view-space units) that is not blocked by nearer
+//   geometry in the depth buffer.  `screen` maps view space to rasterizer
+//   coordinates (viewport * projection).
+pub fn occlusion(fb: &Framebuffer, screen: &Matrix4<f32>, radius: f32) -> Vec<f32> {
+  let kernel = kernel();
+
+  (0..fb.fragments.len()).into_par_iter().map(|idx| {
+    let depth = match fb.depth(idx) {
+      Some(d) => d,
+      None => return 1.0,
+    };
+    let p = fb.position[idx];
+    let n = fb.normal[idx];
+
+    // Orthonormal basis around the normal.
+    let helper = match n.x.abs() > 0.9 {
+      true => Vector3::y(),
+      false => Vector3::x(),
+    };
+    let t = helper.cross(&n).normalize();
+    let b = n.cross(&t);
+
+    let mut occluded = 0.0;
+    for k in &kernel {
+      let sample = p + (t * k.x + b * k.y + n * k.z) * radius;
+      let s = screen * sample.push(1.0);
+      let hit = match fb.index((s.x / s.w) as i32, (s.y / s.w) as i32).and_then(|i| fb.depth(i)) {
+        Some(d) => d,
+        None => continue,
+      };
+
+      if hit < sample.z - BIAS * radius {
+        // Ignore occluders far outside the sampling radius.
+        let range = (radius / (depth - hit).abs().max(1e-4)).clamp(0.0, 1.0);
+        occluded += range * range * (3.0 - 2.0 * range);
+      }
+    }
+
+    1.0 - occluded / SAMPLES as f32
+  }).collect()
+}
+
+// Points in the unit +z hemisphere, spread with the golden angle and
+//   concentrated towards the origin.
+fn kernel() -> Vec<Vector3<f32>> {
+  let golden = std::f32::consts::PI * (3.0 - 5f32.sqrt());
+  (0..SAMPLES).map(|i| {
+    let f = (i as f32 + 0.5) / SAMPLES as f32;
+    let z = 0.1 + 0.9 * ((i as f32 * 0.618034) % 1.0);
+    let r = (1.0 - z * z).sqrt();
+    let phi = golden * i as f32;
+    let scale = 0.1 + 0.9 * f * f;
+    Vector3::new(r * phi.cos(), r * phi.sin(), z) * scale
+  }).collect()
+}
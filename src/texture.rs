@@ -1,17 +1,66 @@
 /**
- * texture.rs: TGA parser and utility functions.
+ * texture.rs: Mipmapped texture sampling and utility functions.
  */
 
 use std::{fs::{self, File}, io::Read};
 
-use tinytga::{RawTga, RawPixel};
 use nalgebra::{Vector2, Vector4};
 
+use crate::color;
+use crate::image;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+  Nearest,
+  Bilinear,
+  // Bilinear within, and linear between, the two nearest mipmap levels.
+  Trilinear,
+}
+
+// Addressing of texel coordinates outside [0, 1].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Wrap {
+  Repeat,
+  MirroredRepeat,
+  ClampToEdge,
+}
+
+impl Wrap {
+  fn apply(&self, i: i64, n: usize) -> usize {
+    let n = n as i64;
+    (match self {
+      Wrap::Repeat => i.rem_euclid(n),
+      Wrap::MirroredRepeat => {
+        let m = i.rem_euclid(2 * n);
+        match m < n {
+          true => m,
+          false => 2 * n - 1 - m,
+        }
+      },
+      Wrap::ClampToEdge => i.clamp(0, n - 1),
+    }) as usize
+  }
+}
+
+// One mipmap level, with linear texels in [0, 1].
+#[derive(Debug)]
+struct Level {
+  width: usize,
+  height: usize,
+  texels: Vec<Vector4<f32>>,
+}
+
 #[derive(Debug)]
 pub struct Texture {
   width: f32,
   height: f32,
-  pixels: Vec<RawPixel>,
+  levels: Vec<Level>,
+  pub filter: Filter,
+  // Addressing along u and v.
+  pub wrap: [Wrap; 2],
+  // Whether the file holds sRGB-encoded colors, which are decoded to linear
+  //   on load.  Data such as normals is stored linearly.
+  srgb: bool,
   pub loaded: bool,
 }
 
@@ -20,40 +69,135 @@ impl Texture {
     Texture {
       width: 0.0,
       height: 0.0,
-      pixels: vec![],
+      levels: vec![],
+      filter: Filter::Trilinear,
+      wrap: [Wrap::Repeat; 2],
+      srgb: false,
       loaded: false,
     }
   }
 
+  // Color texture, decoded from sRGB on load.
+  pub fn srgb() -> Texture {
+    let mut out = Texture::new();
+    out.srgb = true;
+    out
+  }
+
   pub fn load(&mut self, filename: &String) {
     let mut f = File::open(filename).expect("File does not exist");
     let metadata = fs::metadata(filename).expect("Unable to read metadata");
     let mut buf = vec![0; metadata.len() as usize];
     f.read_exact(&mut buf).expect("Buffer overflow");
 
-    let tga: RawTga = RawTga::from_slice(&buf[..]).unwrap();
-    self.width = tga.header().width as f32;
-    self.height = tga.header().height as f32;
-    self.pixels = tga.pixels().collect();
+    let mut image = match image::decode(&buf) {
+      Ok(image) => image,
+      Err(e) => panic!("Unable to load {}: {}", filename, e),
+    };
+    if self.srgb && !image.linear {
+      for t in image.texels.iter_mut() {
+        *t = color::decode(t.xyz()).push(t.w);
+      }
+    }
+    self.width = image.width as f32;
+    self.height = image.height as f32;
+
+    self.levels = vec![Level {
+      width: image.width,
+      height: image.height,
+      texels: image.texels,
+    }];
+    while let Some(next) = self.levels.last().and_then(Level::downsample) {
+      self.levels.push(next);
+    }
     self.loaded = true;
   }
-  pub fn get(&self, vec: Vector2<f32>) -> Vector4<f32> {
-    let x = (vec.x * self.width).floor();
-    let y = (vec.y * self.height).floor();
-
-    match self.pixels.get(((y * self.width) + x) as usize) {
-      Some(x) => {
-        let r = (x.color >> 16) & 0xff;
-        let g = (x.color >> 8) & 0xff;
-        let b = x.color & 0xff;
-        Vector4::new(
-          r as f32,
-          g as f32,
-          b as f32,
-          1.0
-        )
+
+  // Filtered lookup.  `ddx` and `ddy` are the change in UV between
+  //   neighboring pixels, from which the mipmap level is chosen.
+  pub fn sample(&self, vec: Vector2<f32>, ddx: Vector2<f32>, ddy: Vector2<f32>) -> Vector4<f32> {
+    if self.levels.is_empty() {
+      return Vector4::zeros();
+    }
+
+    match self.filter {
+      Filter::Nearest => self.levels[0].nearest(vec, self.wrap),
+      Filter::Bilinear => self.levels[0].bilinear(vec, self.wrap),
+      Filter::Trilinear => {
+        let size = Vector2::new(self.width, self.height);
+        let footprint = ddx.component_mul(&size).norm().max(ddy.component_mul(&size).norm());
+        self.sample_lod(vec, footprint.max(1.0).log2())
+      },
+    }
+  }
+
+  // Trilinear lookup at an explicit mipmap level, where 0 is full
+  //   resolution and `max_lod()` is 1x1.
+  pub fn sample_lod(&self, vec: Vector2<f32>, lod: f32) -> Vector4<f32> {
+    if self.levels.is_empty() {
+      return Vector4::zeros();
+    }
+
+    let lod = lod.clamp(0.0, self.max_lod());
+    let lo = lod.floor() as usize;
+    let hi = (lo + 1).min(self.levels.len() - 1);
+    let t = lod - lo as f32;
+    self.levels[lo].bilinear(vec, self.wrap) * (1.0 - t) + self.levels[hi].bilinear(vec, self.wrap) * t
+  }
+
+  pub fn max_lod(&self) -> f32 {
+    self.levels.len().saturating_sub(1) as f32
+  }
+}
+
+impl Level {
+  fn at(&self, x: usize, y: usize) -> Vector4<f32> {
+    self.texels[x + y * self.width]
+  }
+
+  fn wrapped(&self, x: i64, y: i64, wrap: [Wrap; 2]) -> Vector4<f32> {
+    if self.texels.is_empty() {
+      return Vector4::zeros();
+    }
+    self.at(wrap[0].apply(x, self.width), wrap[1].apply(y, self.height))
+  }
+
+  fn nearest(&self, vec: Vector2<f32>, wrap: [Wrap; 2]) -> Vector4<f32> {
+    let x = (vec.x * self.width as f32).floor() as i64;
+    let y = (vec.y * self.height as f32).floor() as i64;
+    self.wrapped(x, y, wrap)
+  }
+
+  // Blends the four texels around `vec`.
+  fn bilinear(&self, vec: Vector2<f32>, wrap: [Wrap; 2]) -> Vector4<f32> {
+    let x = vec.x * self.width as f32 - 0.5;
+    let y = vec.y * self.height as f32 - 0.5;
+    let (x0, y0) = (x.floor(), y.floor());
+    let (tx, ty) = (x - x0, y - y0);
+    let (x0, y0) = (x0 as i64, y0 as i64);
+
+    let top = self.wrapped(x0, y0, wrap) * (1.0 - tx) + self.wrapped(x0 + 1, y0, wrap) * tx;
+    let bottom = self.wrapped(x0, y0 + 1, wrap) * (1.0 - tx) + self.wrapped(x0 + 1, y0 + 1, wrap) * tx;
+    top * (1.0 - ty) + bottom * ty
+  }
+
+  // Half-resolution 2x2 box filtered copy, or None once 1x1 is reached.
+  fn downsample(&self) -> Option<Level> {
+    if self.width <= 1 && self.height <= 1 {
+      return None;
+    }
+
+    let width = (self.width / 2).max(1);
+    let height = (self.height / 2).max(1);
+    let mut texels = Vec::with_capacity(width * height);
+    for y in 0..height {
+      for x in 0..width {
+        let (x0, y0) = ((x * 2).min(self.width - 1), (y * 2).min(self.height - 1));
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        texels.push((self.at(x0, y0) + self.at(x1, y0) + self.at(x0, y1) + self.at(x1, y1)) / 4.0);
       }
-      None => Vector4::new(0.0, 0.0, 0.0, 0.0)
     }
+
+    Some(Level { width, height, texels })
   }
 }
\ No newline at end of file
@@ -14,6 +14,9 @@ use crate::orbit::Orbit;
 const FPS: u64 = 60;
 const FRAME_INTERVAL: Duration = Duration::from_millis(1000 / FPS);
 
+// Exposure change per keypress, in stops.
+const EXPOSURE_STEP: f32 = 0.5;
+
 pub struct UI<'a> {
   seq: EscSeq,
   orb: Orbit<'a>,
@@ -27,6 +30,12 @@ impl UI<'_> {
     }
   }
   pub fn handle(&mut self) {
+    if self.seq.is_key {
+      self.key(self.seq.command);
+      self.seq.reset();
+      return;
+    }
+
     match self.seq.command {
       'M' => 'M: {
         if !self.seq.is_mouse || self.seq.args.len() < 3 {
@@ -61,17 +70,21 @@ impl UI<'_> {
     }
     self.seq.reset();
   }
+  fn key(&mut self, k: char) {
+    match k {
+      's' => self.orb.shader().toggle_shading(),
+      'w' => self.orb.shader().toggle_wireframe(),
+      '+' | '=' => self.orb.shader().adjust_exposure(EXPOSURE_STEP),
+      '-' => self.orb.shader().adjust_exposure(-EXPOSURE_STEP),
+      _ => return,
+    }
+    self.orb.redraw();
+  }
   pub fn run(&mut self) {
     /* Raw mode */
-    let mut tio = libc::termios {
-      c_iflag: 0,
-      c_oflag: 0,
-      c_cflag: 0,
-      c_lflag: 0,
-      c_cc: [0; 20],
-      c_ispeed: 0,
-      c_ospeed: 0
-    };
+    // termios' fields differ between platforms (e.g. c_line and the size
+    //   of c_cc on Linux), so start from zeroes and let tcgetattr fill it.
+    let mut tio: libc::termios = unsafe { std::mem::zeroed() };
     unsafe {
       libc::tcgetattr(libc::STDIN_FILENO, &mut tio);
 
@@ -106,7 +119,7 @@ impl UI<'_> {
             for b in bytes.iter().take(n) {
               if self.seq.parse_one(*b as char) {
                 let cur = SystemTime::now();
-                if cur.duration_since(last).unwrap() > FRAME_INTERVAL {
+                if self.seq.is_key || cur.duration_since(last).unwrap() > FRAME_INTERVAL {
                   self.handle();
                   last = cur;
                 } else {
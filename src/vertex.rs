@@ -3,8 +3,9 @@
  */
 
 use crate::shader::Shader;
+use crate::framebuffer::{Framebuffer, Fragment};
 use std::cmp::{min, max};
-use nalgebra::{Vector2, Vector3, Vector4, Matrix2x3, Matrix3};
+use nalgebra::{Vector2, Vector3, Vector4, Matrix2x3, Matrix3, Matrix4};
 
 #[derive(Debug)]
 pub struct Vertex {
@@ -26,12 +27,53 @@ impl Vertex {
 
   pub fn triangle(
     &self,
-    s: &Shader
-  ) -> Vec<(i32, f32, String)> {
+    s: &Shader,
+    fb: &Framebuffer,
+    face: usize
+  ) -> Vec<(usize, Fragment)> {
+    let mut out = vec![];
+    self.rasterize(&s.viewport, s.width, s.height, |x, y, bar, depth| {
+      if let Some(idx) = fb.index(x, y) {
+        out.push((idx, Fragment { face, depth, bar }));
+      }
+    });
+    out
+  }
+
+  // Calls `f(x, y, depth)` for every pixel along the triangle's edges.
+  pub fn edges<F>(
+    &self,
+    viewport: &Matrix4<f32>,
+    mut f: F
+  ) where F: FnMut(i32, i32, f32) {
+    let pts: Vec<Vector3<f32>> = self.clip.iter().map(|c| {
+      let p = viewport * c;
+      Vector3::new(p.x / p.w, p.y / p.w, c.z)
+    }).collect();
+
+    for i in 0..3 {
+      let (a, b) = (pts[i], pts[(i + 1) % 3]);
+      let steps = (b.x - a.x).abs().max((b.y - a.y).abs()).ceil().max(1.0) as i32;
+      for step in 0..=steps {
+        let p = a.lerp(&b, step as f32 / steps as f32);
+        f(p.x.round() as i32, p.y.round() as i32, p.z);
+      }
+    }
+  }
+
+  // Calls `f(x, y, barycentric, depth)` for every pixel covered by the
+  //   triangle, with perspective-correct barycentric coordinates.
+  pub fn rasterize<F>(
+    &self,
+    viewport: &Matrix4<f32>,
+    width: i32,
+    height: i32,
+    mut f: F
+  ) where F: FnMut(i32, i32, Vector3<f32>, f32) {
     let pts1 = [
-      s.viewport * self.clip[0],
-      s.viewport * self.clip[1],
-      s.viewport * self.clip[2],
+      viewport * self.clip[0],
+      viewport * self.clip[1],
+      viewport * self.clip[2],
     ];
     let pts2 = vec![
       Vector2::new(pts1[0].x / pts1[0].w, pts1[0].y / pts1[0].w),
@@ -39,7 +81,7 @@ impl Vertex {
       Vector2::new(pts1[2].x / pts1[2].w, pts1[2].y / pts1[2].w),
     ];
 
-    let mut bboxmin = Vector2::new(s.width, s.height);
+    let mut bboxmin = Vector2::new(width, height);
     let mut bboxmax = Vector2::new(1, 1);
     for vec in pts2.iter().take(3) {
       bboxmin.x = min(bboxmin.x, vec.x as i32);
@@ -50,12 +92,11 @@ impl Vertex {
     }
 
     let xmin = max(bboxmin.x, 1);
-    let xmax = min(bboxmax.x, s.width) + 2;
+    let xmax = min(bboxmax.x, width) + 2;
 
     let ymin = max(bboxmin.y, 1);
-    let ymax = min(bboxmax.y, s.height) + 2;
+    let ymax = min(bboxmax.y, height) + 2;
 
-    let mut out = vec![];
     for x in xmin..xmax {
       for y in ymin..ymax {
         let p = Vector2::new(x as f32, y as f32);
@@ -73,27 +114,9 @@ impl Vertex {
         bc_clip /= bc_clip.x + bc_clip.y + bc_clip.z;
 
         let frag_depth = Vector4::new(self.clip[0].z, self.clip[1].z, self.clip[2].z, 0.0).dot(&bc_clip);
-        let z_idx = x + (s.height - y) * s.width;
-
-        let v = s.fragment(self, (s.model_view * bc_clip).xyz(), bc_clip.xyz());
-        if v.w == 0.0 {
-          continue;
-        }
-
-        let seq = format!(
-          "\x1b[48;2;{};{};{}m ",
-          (v.w * v.x) as i32,
-          (v.w * v.y) as i32,
-          (v.w * v.z) as i32
-        );
-        out.push((
-          z_idx,
-          frag_depth,
-          seq
-        ));
+        f(x, y, bc_clip.xyz(), frag_depth);
       }
     }
-    out
   }
 }
 